@@ -0,0 +1,257 @@
+//! Clipboard used by the copy, cut and paste commands.
+//!
+//! Copied text is always kept in an internal kill ring, so that older entries can be pasted
+//! again. Depending on the backend, it is also sent to the system clipboard, either through the
+//! OSC 52 escape sequence, which most terminal emulators support and which also works over ssh,
+//! or through an external helper like `xclip` or `wl-copy`.
+
+use std::collections::VecDeque;
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use errors::*;
+
+/// Maximum number of entries in the kill ring.
+const KILL_RING_SIZE: usize = 16;
+
+/// Where copied text goes, in addition to the kill ring.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// The kill ring only
+    Internal,
+    /// The terminal clipboard, through the OSC 52 escape sequence. Terminals rarely allow
+    /// reading the clipboard this way, so pasting uses the kill ring.
+    Osc52,
+    /// The X11 clipboard, through `xclip`
+    Xclip,
+    /// The Wayland clipboard, through `wl-copy` and `wl-paste`
+    WlCopy,
+}
+
+impl Backend {
+    /// Pick a backend from the environment: the helper of the running display server if there is
+    /// one and it is installed, or OSC 52 otherwise.
+    pub fn detect() -> Backend {
+        if env::var_os("WAYLAND_DISPLAY").is_some() && is_installed("wl-copy") {
+            Backend::WlCopy
+        } else if env::var_os("DISPLAY").is_some() && is_installed("xclip") {
+            Backend::Xclip
+        } else {
+            Backend::Osc52
+        }
+    }
+
+    /// Return the command that writes its standard input to the system clipboard.
+    fn copy_command(&self) -> Option<(&'static str, &'static [&'static str])> {
+        match *self {
+            Backend::Xclip => Some(("xclip", &["-selection", "clipboard", "-in"])),
+            Backend::WlCopy => Some(("wl-copy", &[])),
+            Backend::Internal | Backend::Osc52 => None,
+        }
+    }
+
+    /// Return the command that writes the content of the system clipboard to its standard
+    /// output.
+    fn paste_command(&self) -> Option<(&'static str, &'static [&'static str])> {
+        match *self {
+            Backend::Xclip => Some(("xclip", &["-selection", "clipboard", "-out"])),
+            Backend::WlCopy => Some(("wl-paste", &["--no-newline"])),
+            Backend::Internal | Backend::Osc52 => None,
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Backend> {
+        match s {
+            "internal" => Ok(Backend::Internal),
+            "osc52" => Ok(Backend::Osc52),
+            "xclip" => Ok(Backend::Xclip),
+            "wl-copy" => Ok(Backend::WlCopy),
+            _ => bail!(ErrorKind::InvalidOption("clipboard".into(), s.into())),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Clipboard {
+    backend: Backend,
+    /// Copied texts, the most recent first.
+    ring: VecDeque<String>,
+    /// Whether the last input was a paste, in which case `paste_previous` can replace the pasted
+    /// text.
+    just_pasted: bool,
+    /// OSC 52 escape sequence that the screen must write to the terminal.
+    escape_sequence: Option<String>,
+}
+
+impl Clipboard {
+    pub fn new(backend: Backend) -> Self {
+        Clipboard {
+            backend: backend,
+            ring: VecDeque::new(),
+            just_pasted: false,
+            escape_sequence: None,
+        }
+    }
+
+    /// Store `text` in the kill ring and in the system clipboard.
+    pub fn copy(&mut self, text: String) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.push(text.clone());
+
+        if let Some((program, args)) = self.backend.copy_command() {
+            let spawned = Command::new(program)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn();
+            let mut child = match spawned {
+                Ok(child) => child,
+                Err(e) => {
+                    // The helper was chosen explicitly, or uninstalled since. The terminal may
+                    // still be able to set the clipboard.
+                    error!("failed to run {}: {}, falling back to OSC 52", program, e);
+                    self.backend = Backend::Osc52;
+                    self.escape_sequence = Some(osc52(&text));
+                    return Ok(());
+                }
+            };
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin
+                    .write_all(text.as_bytes())
+                    .chain_err(|| ErrorKind::ClipboardError)?;
+            }
+            // Both helpers fork to serve the clipboard in the background once their input is
+            // closed, so this doesn't block.
+            let status = child.wait().chain_err(|| ErrorKind::ClipboardError)?;
+            if !status.success() {
+                error!("{} exited with status {}", program, status);
+                bail!(ErrorKind::ClipboardError);
+            }
+        }
+        if self.backend == Backend::Osc52 {
+            self.escape_sequence = Some(osc52(&text));
+        }
+        Ok(())
+    }
+
+    /// Return the escape sequence that sets the terminal clipboard to the text copied last, if
+    /// it wasn't written yet. The screen writes it between two frames, so that it isn't mixed
+    /// with the cells of a frame.
+    pub fn take_escape_sequence(&mut self) -> Option<String> {
+        self.escape_sequence.take()
+    }
+
+    /// Return the text to paste: the content of the system clipboard if we can read it, or the
+    /// most recent entry of the kill ring.
+    pub fn paste(&mut self) -> Option<String> {
+        if let Some(text) = self.read_system_clipboard() {
+            if !text.is_empty() && self.ring.front() != Some(&text) {
+                self.push(text);
+            }
+        }
+        self.just_pasted = !self.ring.is_empty();
+        self.ring.front().cloned()
+    }
+
+    /// Rotate the kill ring and return its new most recent entry, which is the entry that was
+    /// copied before the one that was just pasted.
+    pub fn paste_previous(&mut self) -> Option<String> {
+        if let Some(text) = self.ring.pop_front() {
+            self.ring.push_back(text);
+        }
+        self.just_pasted = !self.ring.is_empty();
+        self.ring.front().cloned()
+    }
+
+    /// Return whether the last input was a paste, and forget it.
+    pub fn take_just_pasted(&mut self) -> bool {
+        ::std::mem::replace(&mut self.just_pasted, false)
+    }
+
+    fn push(&mut self, text: String) {
+        self.ring.push_front(text);
+        self.ring.truncate(KILL_RING_SIZE);
+    }
+
+    fn read_system_clipboard(&self) -> Option<String> {
+        let (program, args) = self.backend.paste_command()?;
+        match Command::new(program).args(args).stderr(Stdio::null()).output() {
+            Ok(ref output) if output.status.success() => {
+                Some(String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+            Ok(output) => {
+                error!("{} exited with status {}", program, output.status);
+                None
+            }
+            Err(e) => {
+                error!("failed to run {}: {}", program, e);
+                None
+            }
+        }
+    }
+}
+
+/// Return whether `program` is in one of the directories of `PATH`.
+fn is_installed(program: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+/// Return the OSC 52 escape sequence that sets the clipboard to `text`.
+fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[test]
+fn osc52_sequence() {
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"f"), "Zg==");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foo"), "Zm9v");
+    assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    assert_eq!(osc52("hi\n"), "\x1b]52;c;aGkK\x07");
+}
+
+#[test]
+fn kill_ring() {
+    let mut clipboard = Clipboard::new(Backend::Internal);
+    assert_eq!(clipboard.paste(), None);
+    for text in &["foo", "bar", "baz"] {
+        clipboard.copy(text.to_string()).unwrap();
+    }
+    assert_eq!(clipboard.paste(), Some("baz".into()));
+    assert_eq!(clipboard.paste_previous(), Some("bar".into()));
+    assert_eq!(clipboard.paste_previous(), Some("foo".into()));
+    assert_eq!(clipboard.paste_previous(), Some("baz".into()));
+}
@@ -0,0 +1,215 @@
+use std::env;
+use std::str::FromStr;
+
+use termion::color;
+
+use errors::*;
+
+/// The colors a terminal can display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSupport {
+    /// 24-bit colors
+    TrueColor,
+    /// The xterm 256 colors palette
+    Ansi256,
+    /// The 16 basic ANSI colors
+    Ansi16,
+    /// No colors at all
+    Mono,
+}
+
+impl ColorSupport {
+    /// Guess the color support of the terminal from the `COLORTERM` and `TERM` environment
+    /// variables.
+    pub fn detect() -> ColorSupport {
+        let colorterm = env::var("COLORTERM").ok();
+        let term = env::var("TERM").ok();
+        ColorSupport::from_env(
+            colorterm.as_ref().map(|s| s.as_str()),
+            term.as_ref().map(|s| s.as_str()),
+        )
+    }
+
+    fn from_env(colorterm: Option<&str>, term: Option<&str>) -> ColorSupport {
+        if let Some("truecolor") | Some("24bit") = colorterm {
+            return ColorSupport::TrueColor;
+        }
+        match term {
+            None | Some("") | Some("dumb") => ColorSupport::Mono,
+            Some(term) if term.ends_with("-direct") => ColorSupport::TrueColor,
+            Some(term) if term.contains("256color") => ColorSupport::Ansi256,
+            Some(_) => ColorSupport::Ansi16,
+        }
+    }
+
+    /// Return the escape sequence to set the foreground color to the ARGB color `argb`.
+    pub fn fg(&self, argb: u32) -> String {
+        match *self {
+            ColorSupport::TrueColor => format!("{}", color::Fg(to_rgb(argb))),
+            ColorSupport::Ansi256 => format!("{}", color::Fg(color::AnsiValue(to_ansi256(argb)))),
+            ColorSupport::Ansi16 => ansi16_escape(to_ansi16(argb), 30),
+            ColorSupport::Mono => String::new(),
+        }
+    }
+
+    /// Return the escape sequence to set the background color to the ARGB color `argb`.
+    pub fn bg(&self, argb: u32) -> String {
+        match *self {
+            ColorSupport::TrueColor => format!("{}", color::Bg(to_rgb(argb))),
+            ColorSupport::Ansi256 => format!("{}", color::Bg(color::AnsiValue(to_ansi256(argb)))),
+            ColorSupport::Ansi16 => ansi16_escape(to_ansi16(argb), 40),
+            ColorSupport::Mono => String::new(),
+        }
+    }
+}
+
+impl FromStr for ColorSupport {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ColorSupport> {
+        match s {
+            "truecolor" | "24bit" => Ok(ColorSupport::TrueColor),
+            "256" => Ok(ColorSupport::Ansi256),
+            "16" => Ok(ColorSupport::Ansi16),
+            "mono" | "none" => Ok(ColorSupport::Mono),
+            _ => bail!(ErrorKind::InvalidOption("colors".into(), s.into())),
+        }
+    }
+}
+
+/// The xterm default values of the 16 ANSI colors.
+const ANSI16_PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x00, 0x00),
+    (0x00, 0xcd, 0x00),
+    (0xcd, 0xcd, 0x00),
+    (0x00, 0x00, 0xee),
+    (0xcd, 0x00, 0xcd),
+    (0x00, 0xcd, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x5c, 0x5c, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+/// Intensity of the 6 levels of each component in the xterm 6x6x6 color cube.
+const CUBE_LEVELS: [u8; 6] = [0x00, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
+
+/// Split an ARGB color into its red, green and blue components.
+pub fn components(argb: u32) -> (u8, u8, u8) {
+    ((argb >> 16) as u8, (argb >> 8) as u8, argb as u8)
+}
+
+fn to_rgb(argb: u32) -> color::Rgb {
+    let (r, g, b) = components(argb);
+    color::Rgb(r, g, b)
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| {
+        let d = i32::from(x) - i32::from(y);
+        (d * d) as u32
+    };
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+/// Return the index of the cube level closest to `value`.
+fn cube_index(value: u8) -> usize {
+    CUBE_LEVELS
+        .iter()
+        .enumerate()
+        .min_by_key(|&(_, level)| (i32::from(*level) - i32::from(value)).abs())
+        .map(|(index, _)| index)
+        .unwrap_or(0)
+}
+
+/// Map an ARGB color to the closest entry of the xterm 256 colors palette. Only the color cube
+/// (16-231) and the grayscale ramp (232-255) are considered, since the first 16 colors vary from
+/// one terminal to another.
+pub fn to_ansi256(argb: u32) -> u8 {
+    let rgb = components(argb);
+
+    let (ri, gi, bi) = (cube_index(rgb.0), cube_index(rgb.1), cube_index(rgb.2));
+    let cube_color = (CUBE_LEVELS[ri], CUBE_LEVELS[gi], CUBE_LEVELS[bi]);
+    let cube_value = (16 + 36 * ri + 6 * gi + bi) as u8;
+
+    // The grayscale ramp goes from 0x08 to 0xee by steps of 10
+    let average = (u32::from(rgb.0) + u32::from(rgb.1) + u32::from(rgb.2)) / 3;
+    let gray_index = if average < 8 {
+        0
+    } else {
+        ::std::cmp::min((average - 8 + 5) / 10, 23)
+    };
+    let gray_level = (8 + 10 * gray_index) as u8;
+    let gray_value = (232 + gray_index) as u8;
+
+    if distance(rgb, (gray_level, gray_level, gray_level)) < distance(rgb, cube_color) {
+        gray_value
+    } else {
+        cube_value
+    }
+}
+
+/// Map an ARGB color to the closest of the 16 ANSI colors.
+pub fn to_ansi16(argb: u32) -> u8 {
+    let rgb = components(argb);
+    ANSI16_PALETTE
+        .iter()
+        .enumerate()
+        .min_by_key(|&(_, color)| distance(rgb, *color))
+        .map(|(index, _)| index as u8)
+        .unwrap_or(0)
+}
+
+/// Return the SGR sequence for one of the 16 ANSI colors. `base` is 30 for the foreground and 40
+/// for the background. We don't use the 256 colors sequence, because some terminals like the Linux
+/// console don't support it.
+fn ansi16_escape(index: u8, base: u8) -> String {
+    if index < 8 {
+        format!("\x1b[{}m", base + index)
+    } else {
+        format!("\x1b[{}m", base + 60 + index - 8)
+    }
+}
+
+#[test]
+fn detect_color_support() {
+    assert_eq!(
+        ColorSupport::from_env(Some("truecolor"), Some("xterm")),
+        ColorSupport::TrueColor
+    );
+    assert_eq!(
+        ColorSupport::from_env(None, Some("screen-256color")),
+        ColorSupport::Ansi256
+    );
+    assert_eq!(
+        ColorSupport::from_env(None, Some("linux")),
+        ColorSupport::Ansi16
+    );
+    assert_eq!(ColorSupport::from_env(None, Some("dumb")), ColorSupport::Mono);
+    assert_eq!(ColorSupport::from_env(None, None), ColorSupport::Mono);
+}
+
+#[test]
+fn quantize_ansi256() {
+    assert_eq!(to_ansi256(0xff_00_00_00), 16);
+    assert_eq!(to_ansi256(0xff_ff_ff_ff), 231);
+    assert_eq!(to_ansi256(0xff_ff_00_00), 196);
+    assert_eq!(to_ansi256(0xff_5f_87_af), 67);
+    // Grays are better approximated by the grayscale ramp
+    assert_eq!(to_ansi256(0xff_80_80_80), 244);
+    assert_eq!(to_ansi256(0xff_12_12_12), 233);
+}
+
+#[test]
+fn quantize_ansi16() {
+    assert_eq!(to_ansi16(0xff_00_00_00), 0);
+    assert_eq!(to_ansi16(0xff_f0_10_10), 9);
+    assert_eq!(to_ansi16(0xff_c0_c0_c0), 7);
+    assert_eq!(to_ansi16(0xff_00_00_a0), 4);
+}
@@ -0,0 +1,177 @@
+//! Commands typed in the command prompt, like `open <path>` or `set wrap on`.
+
+use std::fs;
+use std::path::Path;
+
+use color::ColorSupport;
+use core::Core;
+use errors::*;
+use input;
+use keymap::Command;
+use screen::Screen;
+use settings::LineNumbers;
+
+/// Names of the commands, for completion.
+const COMMANDS: &[&str] = &["goto", "open", "q", "set", "theme", "w"];
+
+/// Names of the options of the `set` command.
+const OPTIONS: &[&str] = &[
+    "colors",
+    "highlight_line",
+    "line_numbers",
+    "show_tabs",
+    "tab_width",
+    "wrap",
+];
+
+/// Run a command line typed in the prompt.
+pub fn run(line: &str, core: &mut Core, screen: &mut Screen) -> Result<()> {
+    let line = line.trim().trim_start_matches(':');
+    let (command, argument) = split_command(line);
+    let invalid = || ErrorKind::InvalidCommand(line.into());
+
+    match command {
+        "" => Ok(()),
+        "open" | "e" => {
+            if argument.is_empty() {
+                bail!(invalid());
+            }
+            core.open(argument)
+        }
+        "w" if argument.is_empty() => input::execute(Command::Save, core, screen),
+        "w" => input::save_as(argument, core, screen),
+        "q" => input::execute(Command::CloseBuffer, core, screen),
+        "goto" => {
+            let line: u64 = argument.parse().chain_err(&invalid)?;
+            core.goto_line(line.saturating_sub(1))
+        }
+        "set" => {
+            set_option(core, argument).chain_err(&invalid)?;
+            screen.schedule_update();
+            Ok(())
+        }
+        "theme" => {
+            if argument.is_empty() {
+                bail!(invalid());
+            }
+            core.set_theme(argument)
+        }
+        _ => bail!(invalid()),
+    }
+}
+
+/// Split a command line into the command name and its argument.
+fn split_command(line: &str) -> (&str, &str) {
+    match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    }
+}
+
+/// Set one of the display settings, from the argument of the `set` command: an option name and a
+/// value. The value of boolean options can be omitted to turn them on.
+fn set_option(core: &mut Core, argument: &str) -> Result<()> {
+    let (option, value) = split_command(argument);
+    let invalid = || ErrorKind::InvalidOption(option.into(), value.into());
+    let mut settings = core.settings().clone();
+    match option {
+        "colors" => settings.colors = value.parse::<ColorSupport>()?,
+        "line_numbers" => settings.line_numbers = value.parse::<LineNumbers>()?,
+        "highlight_line" => settings.highlight_line = parse_bool(value).ok_or_else(invalid)?,
+        "show_tabs" => settings.show_tabs = parse_bool(value).ok_or_else(invalid)?,
+        "wrap" => settings.wrap = parse_bool(value).ok_or_else(invalid)?,
+        // "auto" goes back to the tab size configured in the core
+        "tab_width" if value == "auto" => settings.tab_width = None,
+        "tab_width" => settings.tab_width = Some(value.parse().chain_err(invalid)?),
+        _ => bail!(invalid()),
+    }
+    core.update_settings(|s| *s = settings);
+    Ok(())
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "" | "on" | "true" | "yes" => Some(true),
+        "off" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Return the candidates to complete the last word of `line`, and the byte offset where that
+/// word starts.
+pub fn complete(line: &str, core: &Core) -> (usize, Vec<String>) {
+    let (command, argument) = split_command(line);
+    let start = line.rfind(char::is_whitespace)
+        .map(|index| index + 1)
+        .unwrap_or(0);
+    let word = &line[start..];
+    if start == 0 {
+        return (start, matching(COMMANDS, word));
+    }
+
+    let candidates = match command {
+        "open" | "e" | "w" => complete_path(word),
+        "theme" => matching(&core.themes, word),
+        "set" => match split_command(argument) {
+            (_, "") if !line.ends_with(char::is_whitespace) => matching(OPTIONS, word),
+            ("", _) => matching(OPTIONS, word),
+            ("colors", _) => matching(&["truecolor", "256", "16", "mono"], word),
+            ("line_numbers", _) => matching(&["none", "absolute", "relative"], word),
+            ("highlight_line", _) | ("show_tabs", _) | ("wrap", _) => {
+                matching(&["on", "off"], word)
+            }
+            ("tab_width", _) => matching(&["auto"], word),
+            _ => vec![],
+        },
+        _ => vec![],
+    };
+    (start, candidates)
+}
+
+/// Return the candidates that start with `word`.
+fn matching<S: AsRef<str>>(candidates: &[S], word: &str) -> Vec<String> {
+    candidates
+        .iter()
+        .map(|candidate| candidate.as_ref())
+        .filter(|candidate| candidate.starts_with(word))
+        .map(|candidate| candidate.to_owned())
+        .collect()
+}
+
+/// Return the paths that start with `word`. Directories end with a `/`, and hidden files are
+/// only returned if `word` designates one.
+pub fn complete_path(word: &str) -> Vec<String> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(index) => word.split_at(index + 1),
+        None => ("", word),
+    };
+    let dir_path = if dir.is_empty() { "." } else { dir };
+    let entries = match fs::read_dir(Path::new(dir_path)) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut paths: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            Some(format!("{}{}{}", dir, name, if is_dir { "/" } else { "" }))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn command_parsing() {
+    assert_eq!(split_command("open  foo bar.rs "), ("open", "foo bar.rs"));
+    assert_eq!(split_command("q"), ("q", ""));
+    assert_eq!(matching(COMMANDS, "t"), vec!["theme".to_owned()]);
+    assert_eq!(matching(OPTIONS, "x"), Vec::<String>::new());
+    assert_eq!(parse_bool("off"), Some(false));
+    assert_eq!(parse_bool(""), Some(true));
+    assert_eq!(parse_bool("2"), None);
+}
@@ -2,129 +2,387 @@
 use std::collections::hash_map::HashMap;
 use std::io::BufReader;
 use std::io::prelude::*;
-use std::process::ChildStdin;
+use std::collections::VecDeque;
+use std::process::{Child, ChildStdin};
 use std::process::Command;
 use std::process::Stdio;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use serde_json;
 use serde_json::Value;
 
-use update::Update;
-use view::View;
+use clipboard::Clipboard;
+use layout::{Direction, Layout, Rect};
+use protocol::{decode, CoreNotification, CoreRequest, EditParams, Message};
+use settings::Settings;
+use style::{Style, Theme};
+use view::{View, Viewport};
 use errors::*;
 
-pub struct Core {
+/// How long we wait for the core to answer a request before giving up.
+const RPC_TIMEOUT_SECS: u64 = 10;
+
+/// Function called with the result of a request, once the core answered it.
+pub type Callback = Box<dyn FnOnce(&mut Core, Result<Value>) -> Result<()>>;
+
+/// A request sent to the core that has not been answered yet.
+struct PendingRequest {
+    sent_at: Instant,
+    callback: Callback,
+}
+
+/// Number of lines of the core's stderr that are kept to be displayed if it crashes.
+const STDERR_TAIL_LINES: usize = 10;
+
+/// The xi-core child process, and the channels its output is forwarded to.
+struct Process {
+    child: Child,
     stdin: ChildStdin,
-    pub update_rx: mpsc::Receiver<Value>,
+    update_rx: mpsc::Receiver<Result<CoreNotification>>,
     rpc_rx: mpsc::Receiver<(u64, ::std::result::Result<Value, Value>)>,
-    rpc_index: u64,
-    current_view: String,
-    views: HashMap<String, View>,
+    /// Last lines written by the core on stderr.
+    stderr_tail: Arc<Mutex<VecDeque<String>>>,
 }
 
-impl Core {
-    pub fn new(executable: &str) -> Core {
-        // spawn the core process
-        let process = Command::new(executable)
+impl Process {
+    fn spawn(executable: &str) -> Result<Process> {
+        let mut child = Command::new(executable)
             .stdout(Stdio::piped())
             .stdin(Stdio::piped())
             .stderr(Stdio::piped())
             .env("RUST_BACKTRACE", "1")
             .spawn()
-            .unwrap_or_else(|e| panic!("failed to execute core: {}", e));
-
+            .chain_err(|| ErrorKind::CoreSpawnError(executable.to_owned()))?;
 
         let (update_tx, update_rx) = mpsc::channel();
         let (rpc_tx, rpc_rx) = mpsc::channel();
 
-        let stdout = process.stdout.unwrap();
+        let stdout = child.stdout.take().unwrap();
 
         thread::spawn(move || for line in BufReader::new(stdout).lines() {
-            let line = line.unwrap();
-            info!("<<< {}", line);
-
-            if let Ok(data) = serde_json::from_slice::<Value>(line.as_bytes()) {
-                let req = data.as_object().unwrap();
-
-                if let (Some(id), Some(result)) = (req.get("id"), req.get("result")) {
-                    rpc_tx
-                        .send((id.as_u64().unwrap(), Ok(result.clone())))
-                        .unwrap();
-                    continue;
-                }
-
-                if let (Some(error), Some(id)) = (req.get("error"), req.get("id")) {
-                    rpc_tx
-                        .send((id.as_u64().unwrap(), Err(error.clone())))
-                        .unwrap();
-                    continue;
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    error!("failed to read core output: {}", e);
+                    break;
                 }
+            };
+            info!("<<< {}", line);
 
-                if let (Some(method), Some(params)) = (req.get("method"), req.get("params")) {
-                    match method.as_str().unwrap() {
-                        "set_style" | "scroll_to" | "update" => {
-                            update_tx.send(json!([method, &params])).unwrap();
-                        }
-                        _ => {
-                            error!("Unknown method {:?}.", method.as_str().unwrap());
-                        }
-                    }
-                    continue;
+            let res = match decode(&line) {
+                Ok(Message::Response(id, result)) => rpc_tx.send((id, result)).is_ok(),
+                Ok(Message::Notification(notification)) => update_tx.send(Ok(notification)).is_ok(),
+                Ok(Message::Ignored(method)) => {
+                    info!("ignoring notification {}", method);
+                    true
                 }
-
-                error!("Unhandled core request");
-            } else {
-                error!("Could deserialize core output as a json object");
+                Err(e) => update_tx.send(Err(e)).is_ok(),
+            };
+            if !res {
+                info!("core message receiver dropped");
+                break;
             }
         });
 
-        let stderr = process.stderr.unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let stderr_tail = Arc::new(Mutex::new(VecDeque::with_capacity(STDERR_TAIL_LINES)));
+        let tail = stderr_tail.clone();
 
         thread::spawn(move || {
             let buf_reader = BufReader::new(stderr);
             for line in buf_reader.lines() {
                 if let Ok(line) = line {
                     error!("[core] {}", line);
+                    if let Ok(mut tail) = tail.lock() {
+                        if tail.len() == STDERR_TAIL_LINES {
+                            tail.pop_front();
+                        }
+                        tail.push_back(line);
+                    }
                 }
             }
         });
 
-        let stdin = process.stdin.unwrap();
+        let stdin = child.stdin.take().unwrap();
 
-        Core {
+        Ok(Process {
+            child: child,
             stdin: stdin,
             update_rx: update_rx,
             rpc_rx: rpc_rx,
+            stderr_tail: stderr_tail,
+        })
+    }
+}
+
+/// Options of a search.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FindOptions {
+    pub case_sensitive: bool,
+    /// The query is a regular expression
+    pub regex: bool,
+}
+
+/// Information about a core process that exited.
+#[derive(Clone, Debug)]
+pub struct CrashReport {
+    pub status: String,
+    /// Last lines the core wrote on stderr before exiting.
+    pub stderr: Vec<String>,
+}
+
+pub struct Core {
+    executable: String,
+    process: Process,
+    /// Set when the core process exited.
+    crash: Option<CrashReport>,
+    rpc_index: u64,
+    /// Requests waiting for a response, indexed by their ID.
+    pending: HashMap<u64, PendingRequest>,
+    current_view: String,
+    views: HashMap<String, View>,
+    /// IDs of the open views, in the order they were opened. This is the order used to cycle
+    /// through the buffers.
+    view_ids: Vec<String>,
+    /// Panes the views are displayed in. The current view is the view of the focused pane.
+    layout: Layout,
+    /// Styles defined by the core. The core sends each style once, for all the views, so the
+    /// views opened later get a copy of the styles defined so far.
+    styles: HashMap<u16, Style>,
+    /// Themes the core can use, as advertised by the `available_themes` notification.
+    pub themes: Vec<String>,
+    /// Number of `new_view` requests that were not answered yet.
+    opening_views: usize,
+    /// Notifications for views that are not registered yet. Responses and notifications are read
+    /// separately, so the first notifications for a view can be received before the response to
+    /// `new_view`. They are applied once the view is registered.
+    early_notifications: Vec<CoreNotification>,
+    pub clipboard: Clipboard,
+    settings: Settings,
+}
+
+impl Core {
+    pub fn new(executable: &str, settings: Settings) -> Result<Core> {
+        Ok(Core {
+            executable: executable.to_owned(),
+            process: Process::spawn(executable)?,
+            crash: None,
             rpc_index: 0,
+            pending: HashMap::new(),
             current_view: "".into(),
             views: HashMap::new(),
+            view_ids: Vec::new(),
+            layout: Layout::new(),
+            styles: HashMap::new(),
+            themes: Vec::new(),
+            opening_views: 0,
+            early_notifications: Vec::new(),
+            clipboard: Clipboard::new(settings.clipboard),
+            settings: settings,
+        })
+    }
+
+    /// Check whether the core process is still running. Return `true` if it just exited, in
+    /// which case a crash report is available from `crash_report()`.
+    pub fn check_process(&mut self) -> bool {
+        if self.crash.is_some() {
+            return false;
         }
+        let status = match self.process.child.try_wait() {
+            Ok(Some(status)) => status.to_string(),
+            Ok(None) => return false,
+            Err(e) => format!("unknown ({})", e),
+        };
+        error!("the core exited with status {}", status);
+        let stderr = self.process
+            .stderr_tail
+            .lock()
+            .map(|tail| tail.iter().cloned().collect())
+            .unwrap_or_else(|_| vec![]);
+        self.crash = Some(CrashReport {
+            status: status,
+            stderr: stderr,
+        });
+        true
     }
 
-    pub fn update(&mut self, update: &Update) -> Result<()> {
-        // FIXME: I think view_id should be an argument here.
-        // We can have updates for any view, not only the current one.
-        info!("Updating current view");
+    pub fn crash_report(&self) -> Option<&CrashReport> {
+        self.crash.as_ref()
+    }
 
-        if let Some(view) = self.views.get_mut(&self.current_view) {
-            view.update_lines(update)
-        } else {
-            error!("View {} not found", &self.current_view);
-            bail!(ErrorKind::UpdateError);
+    /// Spawn a new core process, and reopen all the views in it, with the cursor where it was.
+    pub fn restart(&mut self) -> Result<()> {
+        info!("restarting the core");
+        let process = Process::spawn(&self.executable)?;
+        let mut old_process = ::std::mem::replace(&mut self.process, process);
+        let _ = old_process.child.kill();
+        let _ = old_process.child.wait();
+        self.crash = None;
+
+        // The old core won't answer anymore
+        for (id, request) in self.pending.drain().collect::<Vec<_>>() {
+            let _ = (request.callback)(self, Err(ErrorKind::RpcTimeoutError(id).into()));
+        }
+        self.early_notifications.clear();
+
+        let current_view = self.current_view.clone();
+        let mut views = ::std::mem::replace(&mut self.views, HashMap::new());
+        let view_ids = ::std::mem::replace(&mut self.view_ids, Vec::new());
+        self.current_view = "".into();
+        // The new core defines its own styles
+        self.styles.clear();
+
+        for old_view_id in view_ids {
+            let view = match views.remove(&old_view_id) {
+                Some(view) => view,
+                None => continue,
+            };
+            let make_current = old_view_id == current_view;
+            let cursor = view.cursor();
+            // Scratch buffers were never saved, so they come back empty.
+            let filepath = view.filepath.clone();
+            self.new_view(filepath.clone(), move |core, view_id| {
+                let view_id = view_id?;
+                info!("reopened {:?} as {}", filepath, view_id);
+                core.insert_view(&view_id, filepath);
+                // The new view takes the place of the old one in the panes
+                core.layout.replace_view(&old_view_id, &view_id);
+                if make_current {
+                    core.current_view = view_id.clone();
+                }
+                core.relayout()?;
+                core.notify_edit(&view_id, "click", Some(json!([cursor.0, cursor.1, 0, 1])))
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn try_recv_notification(&mut self) -> Option<Result<CoreNotification>> {
+        self.process.update_rx.try_recv().ok()
+    }
+
+    /// Apply a notification from the core to the view it targets. Return `true` if the current
+    /// view changed and must be redrawn.
+    pub fn handle_notification(&mut self, notification: CoreNotification) -> Result<bool> {
+        match notification {
+            CoreNotification::SetStyle(style) => {
+                self.set_style(&style);
+                return Ok(true);
+            }
+            CoreNotification::AvailableThemes(params) => {
+                info!("available themes: {:?}", params.themes);
+                self.themes = params.themes;
+                return Ok(false);
+            }
+            CoreNotification::ThemeChanged(params) => {
+                info!("theme changed to {}", params.name);
+                let theme = Theme::from(&params.theme);
+                self.update_settings(|settings| settings.theme = theme);
+                return Ok(true);
+            }
+            CoreNotification::Alert(params) => {
+                warn!("alert from the core: {}", params.msg);
+                return Ok(false);
+            }
+            _ => {}
+        }
+
+        let view_id = notification
+            .view_id()
+            .map(|view_id| view_id.to_owned())
+            .ok_or_else(|| {
+                error!("Received notification without a view_id: {:?}", notification);
+                ErrorKind::UpdateError
+            })?;
+        let is_visible = self.layout.pane_of(&view_id).is_some();
+
+        if !self.views.contains_key(&view_id) && self.opening_views > 0 {
+            info!("Delaying notification for view {} until it is registered", view_id);
+            self.early_notifications.push(notification);
+            return Ok(false);
+        }
+        let view = self.views.get_mut(&view_id).ok_or_else(|| {
+            error!("Received notification for unknown view {}", view_id);
+            ErrorKind::UpdateError
+        })?;
+
+        match notification {
+            CoreNotification::Update(params) => {
+                info!("Updating view {}", view_id);
+                view.update_lines(&params.update)?;
+                // Only the current view reports that it's back to its saved state. The flag of
+                // the other views would be stale by the time they become current.
+                if view_id != self.current_view {
+                    view.take_back_to_pristine();
+                }
+            }
+            CoreNotification::ScrollTo(params) => {
+                info!("Updating cursor position for view {}", view_id);
+                view.update_cursor((params.line, params.col));
+            }
+            CoreNotification::ConfigChanged(params) => {
+                info!("Config changed for view {}: {:?}", view_id, params.changes);
+                if let Some(tab_size) = params.changes.tab_size {
+                    view.set_config_tab_width(tab_size);
+                }
+            }
+            CoreNotification::FindStatus(params) => {
+                info!("Find status for view {}: {:?}", view_id, params.queries);
+                let matches = params.queries.iter().filter_map(|query| query.matches).sum();
+                view.set_find_matches(Some(matches));
+            }
+            CoreNotification::PluginStarted(params) => {
+                info!("Plugin {} started for view {}", params.plugin, view_id);
+                return Ok(false);
+            }
+            CoreNotification::PluginStopped(params) => {
+                info!(
+                    "Plugin {} stopped for view {} (code {:?})",
+                    params.plugin,
+                    view_id,
+                    params.code
+                );
+                return Ok(false);
+            }
+            notification => {
+                info!("Ignoring notification from the core: {:?}", notification);
+                return Ok(false);
+            }
         }
+        Ok(is_visible)
     }
 
-    pub fn scroll_to(&mut self, cursor: (u64, u64)) -> Result<()> {
-        info!("Updating cursor position");
+    /// Styles are not attached to a specific view, so they are registered in all the views.
+    pub fn set_style(&mut self, style: &Style) {
+        self.styles.insert(style.id, style.clone());
+        for view in self.views.values_mut() {
+            view.set_style(style.clone());
+        }
+    }
 
-        if let Some(view) = self.get_view_mut() {
-            view.update_cursor(cursor);
-            return Ok(());
+    /// Register the view `view_id` the core just opened, with the styles defined so far.
+    fn insert_view(&mut self, view_id: &str, filepath: Option<String>) {
+        let mut view = View::new(filepath, &self.settings);
+        for style in self.styles.values() {
+            view.set_style(style.clone());
+        }
+        self.views.insert(view_id.to_owned(), view);
+        self.view_ids.push(view_id.to_owned());
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Modify the display settings, and apply them to all the views.
+    pub fn update_settings<F: FnOnce(&mut Settings)>(&mut self, f: F) {
+        f(&mut self.settings);
+        for view in self.views.values_mut() {
+            view.set_settings(&self.settings);
         }
-        error!("View {} not found", self.current_view.as_str());
-        bail!(ErrorKind::UpdateError);
     }
 
     pub fn get_view(&self) -> Option<&View> {
@@ -135,26 +393,247 @@ impl Core {
         self.views.get_mut(&self.current_view)
     }
 
-    /// Build and send a JSON RPC request, returning the associated request ID to pair it with
-    /// the response
-    fn request(&mut self, method: &str, params: Value) -> Result<u64> {
+    pub fn current_view_id(&self) -> &str {
+        self.current_view.as_str()
+    }
+
+    /// Return the open views with their ID, in the order they were opened.
+    pub fn list_views(&self) -> Vec<(&str, &View)> {
+        self.view_ids
+            .iter()
+            .filter_map(|id| self.views.get(id).map(|view| (id.as_str(), view)))
+            .collect()
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Call `f` with the view of each pane, displayed through the viewport of the pane. The panes
+    /// that have their own viewport come last.
+    pub fn for_each_pane<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&mut View) -> Result<()>,
+    {
+        let mut panes: Vec<(bool, usize, String)> = self.layout
+            .panes()
+            .iter()
+            .map(|pane| (pane.viewport.is_some(), pane.id, pane.view_id.clone()))
+            .collect();
+        panes.sort_by_key(|&(own_viewport, _, _)| own_viewport);
+        for (own_viewport, id, view_id) in panes {
+            // Views are opened asynchronously, so a pane may not have its view yet.
+            let view = match self.views.get_mut(&view_id) {
+                Some(view) => view,
+                None => continue,
+            };
+            let viewport = self.layout.pane_mut(id).and_then(|pane| pane.viewport.as_mut());
+            with_viewport(view, viewport, |view| {
+                // The view only knows whether the lines changed since they were last drawn in
+                // its own viewport, so the other panes are drawn again each time.
+                if own_viewport {
+                    view.mark_dirty();
+                }
+                f(view)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Force all the views to be redrawn.
+    pub fn mark_dirty(&mut self) {
+        for view in self.views.values_mut() {
+            view.mark_dirty();
+        }
+    }
+
+    /// Make `view_id` the current view, displayed in the focused pane.
+    pub fn switch_view(&mut self, view_id: &str) -> Result<()> {
+        match self.views.get_mut(view_id) {
+            Some(view) => view.mark_dirty(),
+            None => {
+                error!("View {} not found", view_id);
+                bail!(ErrorKind::UpdateError);
+            }
+        }
+        info!("Switching to view {}", view_id);
+        self.layout.set_view(view_id);
+        self.current_view = view_id.to_owned();
+        self.relayout()
+    }
+
+    /// Resize the views displayed in the panes to the area of their pane, and tell the core which
+    /// region of each view is displayed.
+    fn relayout(&mut self) -> Result<()> {
+        let focused = self.layout.focused().id;
+        let panes: Vec<(usize, String, Rect)> = self.layout
+            .panes()
+            .iter()
+            .map(|pane| (pane.id, pane.view_id.clone(), pane.rect))
+            .collect();
+        let mut view_ids: Vec<&str> = panes.iter().map(|pane| pane.1.as_str()).collect();
+        view_ids.sort();
+        view_ids.dedup();
+
+        for view_id in view_ids {
+            // Views are opened asynchronously, so a pane may not have its view yet.
+            let view = match self.views.get_mut(view_id) {
+                Some(view) => view,
+                None => continue,
+            };
+            let ids: Vec<usize> = panes
+                .iter()
+                .filter(|pane| pane.1 == view_id)
+                .map(|pane| pane.0)
+                .collect();
+            assign_viewports(&mut self.layout, view, &ids, focused);
+
+            // The core sends the lines of the region that covers all the panes of the view.
+            let mut scroll_region: Option<(u64, u64)> = None;
+            for &(id, _, rect) in panes.iter().filter(|pane| pane.1 == view_id) {
+                let viewport = self.layout.pane_mut(id).and_then(|pane| pane.viewport.as_mut());
+                let is_focused = id == focused;
+                let (start, end) = with_viewport(view, viewport, |view| {
+                    view.set_focused(is_focused);
+                    view.resize(rect);
+                    view.get_window()
+                });
+                scroll_region = Some(match scroll_region {
+                    Some((first, last)) => {
+                        (::std::cmp::min(first, start), ::std::cmp::max(last, end))
+                    }
+                    None => (start, end),
+                });
+            }
+            if let Some((start, end)) = scroll_region {
+                self.notify_edit(view_id, "scroll", Some(json!([start, end])))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Split the focused pane, and display the current view in the new pane too. The new pane
+    /// gets the focus.
+    pub fn split(&mut self, direction: Direction) -> Result<()> {
+        if self.get_view().is_none() {
+            error!("View {} not found", &self.current_view);
+            bail!(ErrorKind::UpdateError);
+        }
+        let view_id = self.current_view.clone();
+        self.layout.split(direction, &view_id);
+        self.relayout()
+    }
+
+    /// Close the focused pane, unless it's the only one. Its view stays open.
+    pub fn close_pane(&mut self) -> Result<()> {
+        if self.layout.close_focused() {
+            self.current_view = self.layout.focused().view_id.clone();
+            self.relayout()?;
+        }
+        Ok(())
+    }
+
+    pub fn next_pane(&mut self) -> Result<()> {
+        self.layout.focus_next(1);
+        self.current_view = self.layout.focused().view_id.clone();
+        self.relayout()
+    }
+
+    pub fn prev_pane(&mut self) -> Result<()> {
+        let step = self.layout.len().saturating_sub(1);
+        self.layout.focus_next(step);
+        self.current_view = self.layout.focused().view_id.clone();
+        self.relayout()
+    }
+
+    /// Grow the focused pane by `delta` percent, or shrink it if `delta` is negative.
+    pub fn resize_pane(&mut self, delta: i16) -> Result<()> {
+        self.layout.resize_focused(delta);
+        self.relayout()
+    }
+
+    pub fn next_view(&mut self) -> Result<()> {
+        self.cycle_view(1)
+    }
+
+    pub fn prev_view(&mut self) -> Result<()> {
+        self.cycle_view(self.view_ids.len().saturating_sub(1))
+    }
+
+    fn cycle_view(&mut self, step: usize) -> Result<()> {
+        if self.view_ids.is_empty() {
+            bail!(ErrorKind::UpdateError);
+        }
+        let index = self.view_ids
+            .iter()
+            .position(|id| id == &self.current_view)
+            .unwrap_or(0);
+        let view_id = self.view_ids[(index + step) % self.view_ids.len()].clone();
+        self.switch_view(&view_id)
+    }
+
+    /// Close the current view, and switch to the view that was opened right before it. Return
+    /// `false` if there is no view left.
+    pub fn close_view(&mut self) -> Result<bool> {
+        let view_id = self.current_view.clone();
+        let index = match self.view_ids.iter().position(|id| id == &view_id) {
+            Some(index) => index,
+            None => {
+                error!("View {} not found", &view_id);
+                bail!(ErrorKind::UpdateError);
+            }
+        };
+        self.notify(&CoreRequest::CloseView {
+            view_id: view_id.clone(),
+        })?;
+        // The other panes that display the view are closed with it.
+        let focused = self.layout.focused().id;
+        let other_panes: Vec<usize> = self.layout
+            .panes()
+            .iter()
+            .filter(|pane| pane.view_id == view_id && pane.id != focused)
+            .map(|pane| pane.id)
+            .collect();
+        for id in other_panes {
+            self.layout.close(id);
+        }
+        self.views.remove(&view_id);
+        self.view_ids.remove(index);
+        self.current_view = "".into();
+
+        if self.view_ids.is_empty() {
+            return Ok(false);
+        }
+        // Display a view that no other pane displays, preferably the one opened right before, or
+        // close the pane if there is none.
+        let next_view = self.view_ids[..index]
+            .iter()
+            .rev()
+            .chain(self.view_ids[index..].iter())
+            .find(|id| self.layout.pane_of(id).is_none())
+            .cloned();
+        match next_view {
+            Some(next_view) => self.switch_view(&next_view)?,
+            None => {
+                self.layout.close_focused();
+                let next_view = self.layout.focused().view_id.clone();
+                self.switch_view(&next_view)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Send a JSON RPC request, returning the associated request ID to pair it with the response
+    fn request(&mut self, request: &CoreRequest) -> Result<u64> {
         self.rpc_index += 1;
-        let message = json!({
-            "id": self.rpc_index,
-            "method": method,
-            "params": params,
-        });
+        let message = request.to_message(Some(self.rpc_index))?;
         self.send(&message)?;
         Ok(self.rpc_index)
     }
 
-    /// Build and send a JSON RPC notification. No synchronous response is expected, so
-    /// there is no ID.
-    fn notify(&mut self, method: &str, params: Value) -> Result<()> {
-        let message = json!({
-            "method": method,
-            "params": params,
-        });
+    /// Send a JSON RPC notification. No synchronous response is expected, so there is no ID.
+    fn notify(&mut self, request: &CoreRequest) -> Result<()> {
+        let message = request.to_message(None)?;
         self.send(&message)
     }
 
@@ -166,66 +645,182 @@ impl Core {
         })?;
         info!(">>> {}", &str_msg);
         str_msg.push('\n');
-        self.stdin.write_all(str_msg.as_bytes()).chain_err(|| {
+        self.process.stdin.write_all(str_msg.as_bytes()).chain_err(|| {
             error!("could not write the message to send");
             ErrorKind::RpcError
         })?;
         Ok(())
     }
 
-    fn call_sync(&mut self, method: &str, params: Value) -> Result<Value> {
-        let i = self.request(method, params)?;
-        let (id, result) = self.rpc_rx.recv().unwrap();
-        assert_eq!(i, id);
-        // TODO: in the future, the caller should handle the error. For now we just log it and move
-        // on, returning a generic RpcError.
-        result.or_else(|_| {
-            error!("synchronous call returned with an error");
-            Err(ErrorKind::RpcError.into())
-        })
+    /// Send a request, and register `callback` to be called with the result when the response
+    /// arrives. The callback is called with an error if the core doesn't answer in time.
+    fn call<F>(&mut self, request: &CoreRequest, callback: F) -> Result<()>
+    where
+        F: FnOnce(&mut Core, Result<Value>) -> Result<()> + 'static,
+    {
+        let id = self.request(request)?;
+        self.pending.insert(
+            id,
+            PendingRequest {
+                sent_at: Instant::now(),
+                callback: Box::new(callback),
+            },
+        );
+        Ok(())
+    }
+
+    /// Handle the responses received from the core, and expire the requests that have been
+    /// waiting for too long. Return `true` if at least one callback was called.
+    pub fn process_responses(&mut self) -> Result<bool> {
+        let mut results = vec![];
+        while let Ok((id, result)) = self.process.rpc_rx.try_recv() {
+            let result = result.map_err(|error| {
+                error!("request {} returned with an error: {}", id, error);
+                // Errors are usually objects with a human readable message.
+                let message = match error.get("message").and_then(|message| message.as_str()) {
+                    Some(message) => message.to_owned(),
+                    None => error.to_string(),
+                };
+                ErrorKind::CoreError(message).into()
+            });
+            results.push((id, result));
+        }
+
+        let timeout = Duration::from_secs(RPC_TIMEOUT_SECS);
+        let expired: Vec<u64> = self.pending
+            .iter()
+            .filter(|&(_, request)| request.sent_at.elapsed() > timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            error!("request {} timed out", id);
+            results.push((id, Err(ErrorKind::RpcTimeoutError(id).into())));
+        }
+
+        let mut handled = false;
+        let mut first_error = None;
+        for (id, result) in results {
+            let request = match self.pending.remove(&id) {
+                Some(request) => request,
+                None => {
+                    error!("received a response for unknown request {}", id);
+                    continue;
+                }
+            };
+            handled = true;
+            if let Err(e) = (request.callback)(self, result) {
+                if first_error.is_none() {
+                    first_error = Some(e);
+                } else {
+                    error!("request {} callback failed: {}", id, e);
+                }
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(handled),
+        }
+    }
+
+    fn edit_request(&self, method: &str, params: Option<Value>) -> CoreRequest {
+        edit_request(&self.current_view, method, params)
     }
 
     fn call_edit(&mut self, method: &str, params: Option<Value>) -> Result<()> {
-        let msg = json!({
-            "method": method,
-            "view_id": &self.current_view,
-            "params": params.unwrap_or_else(|| Value::Array(vec![])),
-        });
-        self.notify("edit", msg)
+        let request = self.edit_request(method, params);
+        self.notify(&request)
     }
 
-    fn call_edit_sync(&mut self, method: &str, params: Option<Value>) -> Result<Value> {
-        let msg = json!({
-            "method": method,
-            "view_id": &self.current_view,
-            "params": params.unwrap_or_else(|| Value::Array(vec![])),
-        });
-        self.call_sync("edit", msg)
+    /// Send an edit notification for a view that is not necessarily the current one.
+    fn notify_edit(&mut self, view_id: &str, method: &str, params: Option<Value>) -> Result<()> {
+        self.notify(&edit_request(view_id, method, params))
+    }
+
+    fn call_edit_async<F>(&mut self, method: &str, params: Option<Value>, callback: F) -> Result<()>
+    where
+        F: FnOnce(&mut Core, Result<Value>) -> Result<()> + 'static,
+    {
+        let request = self.edit_request(method, params);
+        self.call(&request, callback)
+    }
+
+    /// Ask the core for a new view. `callback` is called with the ID of the new view.
+    pub fn new_view<F>(&mut self, file_path: Option<String>, callback: F) -> Result<()>
+    where
+        F: FnOnce(&mut Core, Result<String>) -> Result<()> + 'static,
+    {
+        let request = CoreRequest::NewView {
+            file_path: file_path,
+        };
+        self.call(&request, move |core, result| {
+            core.opening_views -= 1;
+            let view_id = result.and_then(|value| {
+                value.as_str().map(|s| s.to_owned()).ok_or_else(|| {
+                    error!("Failed to deserialize \"new_view\" response as string");
+                    ErrorKind::RpcError.into()
+                })
+            });
+            let result = callback(core, view_id);
+            core.handle_early_notifications();
+            result
+        })?;
+        self.opening_views += 1;
+        Ok(())
     }
 
-    pub fn new_view(&mut self, file_path: Option<String>) -> Result<String> {
-        let msg: Value;
-        if let Some(file_path) = file_path {
-            msg = json!({ "file_path": file_path });
-        } else {
-            msg = json!({});
+    /// Apply the notifications that were received before their view was registered. Those for
+    /// views that are still being opened are kept for later.
+    fn handle_early_notifications(&mut self) {
+        let notifications = ::std::mem::replace(&mut self.early_notifications, Vec::new());
+        for notification in notifications {
+            if let Err(e) = self.handle_notification(notification) {
+                error!("failed to handle a delayed notification: {}", e);
+            }
         }
-        self.call_sync("new_view", msg)?
-            .as_str()
-            .map(|s| s.to_owned())
-            .ok_or_else(|| {
-                error!("Failed to deserialize \"new_view\" response as string");
-                ErrorKind::RpcError.into()
-            })
     }
 
-    pub fn save(&mut self) -> Result<Value> {
-        let views = self.views.clone();
-        let save_params = json!({
-            "view_id": &self.current_view.as_str(),
-            "file_path": &views[&self.current_view].filepath.clone(),
-        });
-        self.call_sync("save", save_params)
+    /// Save the current view to its file. Scratch buffers have no file, and must be saved with
+    /// `save_as`.
+    pub fn save(&mut self) -> Result<()> {
+        let filepath = match self.views.get(&self.current_view) {
+            Some(view) => view.filepath.clone(),
+            None => {
+                error!("View {} not found", &self.current_view);
+                bail!(ErrorKind::UpdateError);
+            }
+        };
+        match filepath {
+            Some(filepath) => self.save_as(&filepath),
+            None => bail!(ErrorKind::NoFilePath),
+        }
+    }
+
+    /// Save the current view to `filepath`, which becomes the path of the view once saved.
+    pub fn save_as(&mut self, filepath: &str) -> Result<()> {
+        let view_id = self.current_view.clone();
+        let filepath = filepath.to_owned();
+        let request = CoreRequest::Save {
+            view_id: view_id.clone(),
+            file_path: filepath.clone(),
+        };
+        self.call(&request, move |core, result| {
+            result.chain_err(|| {
+                error!("failed to save {}", filepath);
+                ErrorKind::SaveError(filepath.clone())
+            })?;
+            info!("saved {}", filepath);
+            if let Some(view) = core.views.get_mut(&view_id) {
+                view.filepath = Some(filepath);
+            }
+            Ok(())
+        })
+    }
+
+    pub fn set_theme(&mut self, theme_name: &str) -> Result<()> {
+        self.notify(&CoreRequest::SetTheme {
+            theme_name: theme_name.to_owned(),
+        })
     }
 
     pub fn left(&mut self) -> Result<()> {
@@ -245,7 +840,10 @@ impl Core {
     }
 
     pub fn up(&mut self) -> Result<()> {
-        self.call_edit("move_up", None)
+        match self.move_by_rows(-1) {
+            Some((line, offset)) => self.call_edit("click", Some(json!([line, offset, 0, 1]))),
+            None => self.call_edit("move_up", None),
+        }
     }
 
     pub fn up_sel(&mut self) -> Result<()> {
@@ -253,7 +851,16 @@ impl Core {
     }
 
     pub fn down(&mut self) -> Result<()> {
-        self.call_edit("move_down", None)
+        match self.move_by_rows(1) {
+            Some((line, offset)) => self.call_edit("click", Some(json!([line, offset, 0, 1]))),
+            None => self.call_edit("move_down", None),
+        }
+    }
+
+    /// When lines are wrapped, the core moves the cursor by lines rather than by rows on the
+    /// screen, so we compute the target position ourselves.
+    fn move_by_rows(&self, delta: i64) -> Option<(u64, u64)> {
+        self.get_view().and_then(|view| view.move_by_rows(delta))
     }
 
     pub fn down_sel(&mut self) -> Result<()> {
@@ -264,6 +871,130 @@ impl Core {
         self.call_edit("delete_backward", None)
     }
 
+    pub fn undo(&mut self) -> Result<()> {
+        self.call_edit("undo", None)
+    }
+
+    pub fn redo(&mut self) -> Result<()> {
+        self.call_edit("redo", None)
+    }
+
+    /// Add a cursor on the line above the topmost cursor.
+    pub fn add_cursor_above(&mut self) -> Result<()> {
+        self.call_edit("add_selection_above", None)
+    }
+
+    /// Add a cursor on the line below the bottommost cursor.
+    pub fn add_cursor_below(&mut self) -> Result<()> {
+        self.call_edit("add_selection_below", None)
+    }
+
+    /// Search for the selected text, or for the word under the cursor if nothing is selected, and
+    /// add its next occurrence to the selections.
+    pub fn add_next_occurrence(&mut self) -> Result<()> {
+        self.call_edit("selection_for_find", Some(json!({ "case_sensitive": true })))?;
+        self.call_edit(
+            "find_next",
+            Some(json!({
+                "wrap_around": true,
+                "allow_same": false,
+                "modify_selection": "add",
+            })),
+        )
+    }
+
+    /// Remove all the cursors but the last one.
+    pub fn collapse_cursors(&mut self) -> Result<()> {
+        self.call_edit("collapse_selections", None)
+    }
+
+    /// Move the cursor to the beginning of line `line`, counted from 0.
+    pub fn goto_line(&mut self, line: u64) -> Result<()> {
+        self.call_edit("goto_line", Some(json!({ "line": line })))
+    }
+
+    /// Search for `query` in the current view. The matches are highlighted, but the cursor doesn't
+    /// move until `find_next()` or `find_previous()` is called.
+    pub fn find(&mut self, query: &str, options: FindOptions) -> Result<()> {
+        self.call_edit(
+            "find",
+            Some(json!({
+                "chars": query,
+                "case_sensitive": options.case_sensitive,
+                "regex": options.regex,
+                "whole_words": false,
+            })),
+        )
+    }
+
+    /// Select the next match of the current search. If `allow_same` is true, the match at the
+    /// cursor can be selected.
+    pub fn find_next(&mut self, allow_same: bool) -> Result<()> {
+        self.call_edit(
+            "find_next",
+            Some(json!({ "wrap_around": true, "allow_same": allow_same })),
+        )
+    }
+
+    pub fn find_previous(&mut self) -> Result<()> {
+        self.call_edit("find_previous", Some(json!({ "wrap_around": true })))
+    }
+
+    /// Show or hide the highlighting of the search matches.
+    pub fn highlight_find(&mut self, visible: bool) -> Result<()> {
+        if !visible {
+            if let Some(view) = self.get_view_mut() {
+                view.set_find_matches(None);
+            }
+        }
+        self.call_edit("highlight_find", Some(json!({ "visible": visible })))
+    }
+
+    /// Set the text that replaces the matches of the current search.
+    pub fn replace(&mut self, replacement: &str) -> Result<()> {
+        self.call_edit(
+            "replace",
+            Some(json!({ "chars": replacement, "preserve_case": false })),
+        )
+    }
+
+    /// Replace the selected match and select the next one.
+    pub fn replace_next(&mut self) -> Result<()> {
+        self.call_edit("replace_next", None)
+    }
+
+    pub fn replace_all(&mut self) -> Result<()> {
+        self.call_edit("replace_all", None)
+    }
+
+    pub fn delete_forward(&mut self) -> Result<()> {
+        self.call_edit("delete_forward", None)
+    }
+
+    pub fn line_start(&mut self) -> Result<()> {
+        self.call_edit("move_to_left_end_of_line", None)
+    }
+
+    pub fn line_end(&mut self) -> Result<()> {
+        self.call_edit("move_to_right_end_of_line", None)
+    }
+
+    pub fn word_left(&mut self) -> Result<()> {
+        self.call_edit("move_word_left", None)
+    }
+
+    pub fn word_right(&mut self) -> Result<()> {
+        self.call_edit("move_word_right", None)
+    }
+
+    pub fn document_start(&mut self) -> Result<()> {
+        self.call_edit("move_to_beginning_of_document", None)
+    }
+
+    pub fn document_end(&mut self) -> Result<()> {
+        self.call_edit("move_to_end_of_document", None)
+    }
+
     pub fn page_up(&mut self) -> Result<()> {
         self.call_edit("page_up", None)
     }
@@ -299,69 +1030,162 @@ impl Core {
         self.call_edit("scroll", Some(json!([start, end])))
     }
 
-    pub fn resize(&mut self, height: u16) -> Result<()> {
-        let scroll_region: (u64, u64);
-        if let Some(view) = self.views.get_mut(&self.current_view) {
-            view.resize(height);
-            scroll_region = view.get_window();
-        } else {
-            error!("View {} not found", &self.current_view);
-            bail!(ErrorKind::UpdateError);
-        }
-        self.scroll(scroll_region.0, scroll_region.1)
+    /// Set the size of the area the panes are laid out in. The screen is drawn again from
+    /// scratch, so all the views are redrawn.
+    pub fn resize(&mut self, width: u16, height: u16) -> Result<()> {
+        self.layout.set_area(Rect {
+            x: 0,
+            y: 0,
+            width: width,
+            height: height,
+        });
+        self.mark_dirty();
+        self.relayout()
     }
 
-    pub fn click(&mut self, line: u64, column: u64) -> Result<()> {
-        let lineno: u64;
-        if let Some(view) = self.views.get_mut(&self.current_view) {
-            lineno = line + view.get_window().0;
-        } else {
-            error!("View {} not found", &self.current_view);
-            bail!(ErrorKind::UpdateError);
+    /// Handle a click at the given position on the screen. Clicking in another pane focuses it.
+    pub fn click(&mut self, row: u16, column: u16) -> Result<()> {
+        let (pane, view_id) = match self.layout.pane_at(column, row) {
+            Some(pane) => (pane.id, pane.view_id.clone()),
+            // On a separator
+            None => return Ok(()),
+        };
+        if pane != self.layout.focused().id {
+            self.layout.focus(pane);
+            self.current_view = view_id;
+            self.relayout()?;
         }
-        self.call_edit("click", Some(json!([lineno, column, 0, 1])))
+        let (line, offset) = self.screen_to_buffer(row, column)?;
+        self.call_edit("click", Some(json!([line, offset, 0, 1])))
     }
 
-    pub fn drag(&mut self, line: u64, column: u64) -> Result<()> {
-        let lineno: u64;
-        if let Some(view) = self.views.get_mut(&self.current_view) {
-            lineno = line + view.get_window().0;
-        } else {
-            error!("View {} not found", &self.current_view);
-            bail!(ErrorKind::UpdateError);
+    pub fn drag(&mut self, row: u16, column: u16) -> Result<()> {
+        let (line, offset) = self.screen_to_buffer(row, column)?;
+        self.call_edit("drag", Some(json!([line, offset, 0, 1])))
+    }
+
+    /// Convert a position on the screen to a position in the current view.
+    fn screen_to_buffer(&self, row: u16, column: u16) -> Result<(u64, u64)> {
+        let rect = self.layout.focused().rect;
+        let (row, column) = (row.saturating_sub(rect.y), column.saturating_sub(rect.x));
+        match self.views.get(&self.current_view) {
+            Some(view) => Ok(view.screen_to_buffer(row, column)),
+            None => {
+                error!("View {} not found", &self.current_view);
+                bail!(ErrorKind::UpdateError);
+            }
         }
-        self.call_edit("drag", Some(json!([lineno, column, 0, 1])))
     }
 
-    pub fn copy(&mut self) -> Result<String> {
-        self.call_edit_sync("copy", None)?
-            .as_str()
-            .map(|s| s.to_owned())
-            .ok_or_else(|| {
-                error!("Failed to deserialize \"copy\" response as string");
-                ErrorKind::RpcError.into()
-            })
+    /// Copy the current selection. `callback` is called with the copied text.
+    pub fn copy<F>(&mut self, callback: F) -> Result<()>
+    where
+        F: FnOnce(&mut Core, Result<String>) -> Result<()> + 'static,
+    {
+        self.call_edit_async("copy", None, move |core, result| {
+            callback(core, string_result("copy", result))
+        })
     }
 
-    pub fn cut(&mut self) -> Result<String> {
-        self.call_edit_sync("cut", None)?
-            .as_str()
-            .map(|s| s.to_owned())
-            .ok_or_else(|| {
-                error!("Failed to deserialize \"cut\" response as string");
-                ErrorKind::RpcError.into()
-            })
+    /// Cut the current selection. `callback` is called with the text that was cut.
+    pub fn cut<F>(&mut self, callback: F) -> Result<()>
+    where
+        F: FnOnce(&mut Core, Result<String>) -> Result<()> + 'static,
+    {
+        self.call_edit_async("cut", None, move |core, result| {
+            callback(core, string_result("cut", result))
+        })
     }
 
     pub fn paste(&mut self, s: String) -> Result<()> {
         self.call_edit("insert", Some(json!({ "chars": s })))
     }
 
+    /// Open `filename` in a new view and make it the current view. If the file is already open,
+    /// just switch to the existing view.
     pub fn open(&mut self, filename: &str) -> Result<()> {
-        let view_id = self.new_view(Some(filename.to_owned()))?;
-        let view = View::new(filename);
-        self.views.insert(view_id.clone(), view);
-        self.current_view = view_id;
-        Ok(())
+        let existing = self.view_ids
+            .iter()
+            .find(|id| {
+                self.views[*id].filepath.as_ref().map(|path| path.as_str()) == Some(filename)
+            })
+            .cloned();
+        if let Some(view_id) = existing {
+            return self.switch_view(&view_id);
+        }
+        self.open_view(Some(filename.to_owned()))
+    }
+
+    /// Open an empty buffer that is not associated with any file yet.
+    pub fn open_scratch(&mut self) -> Result<()> {
+        self.open_view(None)
+    }
+
+    fn open_view(&mut self, filename: Option<String>) -> Result<()> {
+        self.new_view(filename.clone(), move |core, view_id| {
+            let view_id = view_id?;
+            core.insert_view(&view_id, filename);
+            core.switch_view(&view_id)
+        })
+    }
+}
+
+/// Give `view` the viewport of the focused pane if it's one of the panes `ids` that display the
+/// view, or else of the first one. The other panes hold their own viewport, which starts as a copy
+/// of the viewport of the view.
+fn assign_viewports(layout: &mut Layout, view: &mut View, ids: &[usize], focused: usize) {
+    let main = if ids.contains(&focused) {
+        focused
+    } else {
+        ids[0]
+    };
+    let mut previous = view.viewport();
+    if let Some(mut viewport) = layout.pane_mut(main).and_then(|pane| pane.viewport.take()) {
+        view.swap_viewport(&mut viewport);
+        previous = viewport;
+    }
+    for &id in ids.iter().filter(|id| **id != main) {
+        if let Some(pane) = layout.pane_mut(id) {
+            if pane.viewport.is_none() {
+                pane.viewport = Some(previous.clone());
+            }
+        }
+    }
+}
+
+/// Call `f` with `view` displayed through `viewport`, if the pane has its own viewport.
+fn with_viewport<T, F>(view: &mut View, viewport: Option<&mut Viewport>, f: F) -> T
+where
+    F: FnOnce(&mut View) -> T,
+{
+    match viewport {
+        Some(viewport) => {
+            view.swap_viewport(viewport);
+            let result = f(view);
+            view.swap_viewport(viewport);
+            result
+        }
+        None => f(view),
+    }
+}
+
+fn edit_request(view_id: &str, method: &str, params: Option<Value>) -> CoreRequest {
+    CoreRequest::Edit(EditParams {
+        method: method.to_owned(),
+        view_id: view_id.to_owned(),
+        params: params.unwrap_or_else(|| Value::Array(vec![])),
+    })
+}
+
+/// Extract the string returned by `method`. The core returns `null` when there is nothing to
+/// copy or cut, which we treat as an empty string.
+fn string_result(method: &str, result: Result<Value>) -> Result<String> {
+    match result? {
+        Value::String(s) => Ok(s),
+        Value::Null => Ok(String::new()),
+        _ => {
+            error!("Failed to deserialize {:?} response as string", method);
+            Err(ErrorKind::RpcError.into())
+        }
     }
 }
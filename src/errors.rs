@@ -14,6 +14,18 @@ error_chain! {
             description("xi-rpc error")
             display("a xi-rpc error occured")
         }
+        RpcTimeoutError(id: u64) {
+            description("xi-rpc request timed out")
+            display("xi-rpc request {} timed out", id)
+        }
+        CoreError(error: String) {
+            description("the core returned an error")
+            display("the core returned an error: {}", error)
+        }
+        CoreSpawnError(executable: String) {
+            description("failed to execute core")
+            display("failed to execute core: {}", executable)
+        }
         DisplayError {
             description("failed to draw screen")
             display("failed to draw screen")
@@ -26,6 +38,38 @@ error_chain! {
             description("cannot handle input")
             display("cannot handle input")
         }
+        InvalidCommand(command: String) {
+            description("invalid command")
+            display("invalid command {:?}", command)
+        }
+        InvalidOption(option: String, value: String) {
+            description("invalid option value")
+            display("invalid value {:?} for option {}", value, option)
+        }
+        SaveError(path: String) {
+            description("failed to save a file")
+            display("failed to save {}", path)
+        }
+        NoFilePath {
+            description("the buffer has no file")
+            display("the buffer has no file, save it with a path")
+        }
+        ClipboardError {
+            description("clipboard error")
+            display("failed to access the clipboard")
+        }
+        ConfigError(path: String) {
+            description("invalid configuration file")
+            display("invalid configuration file {}", path)
+        }
+        InvalidKey(key: String) {
+            description("invalid key name")
+            display("invalid key name {:?}", key)
+        }
+        UndefinedKey(keys: String) {
+            description("the key is not bound to a command")
+            display("{} is undefined", keys)
+        }
         TerminalSizeError {
             description("cannot determine terminal size")
             display("cannot determine terminal size")
@@ -0,0 +1,283 @@
+//! Off-screen grid of cells that everything on the screen is drawn into. Once a frame is drawn,
+//! it is compared with the frame the terminal displays, and only the cells that changed are
+//! written to the terminal, in a single write.
+
+use termion::{clear, cursor};
+use unicode_segmentation::UnicodeSegmentation;
+
+use color::ColorSupport;
+use layout::Rect;
+use settings::DEFAULT_TAB_WIDTH;
+use style::Attributes;
+use width;
+use width::LineLayout;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cell {
+    /// Grapheme cluster displayed in the cell. It is empty for the cell covered by the right half
+    /// of a wide character.
+    pub symbol: String,
+    /// Number of columns taken by the symbol: 1, 2 for a wide character, or 0 for the cell
+    /// covered by the right half of a wide character.
+    pub width: u16,
+    pub attributes: Attributes,
+}
+
+impl Default for Cell {
+    fn default() -> Cell {
+        Cell {
+            symbol: " ".to_owned(),
+            width: 1,
+            attributes: Attributes::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Grid {
+    width: u16,
+    height: u16,
+    /// Cells, row by row
+    cells: Vec<Cell>,
+    /// Position of the terminal cursor, or `None` to hide it.
+    cursor: Option<(u16, u16)>,
+}
+
+impl Grid {
+    /// Return a blank grid, which is what the terminal displays once it's cleared.
+    pub fn new(width: u16, height: u16) -> Grid {
+        Grid {
+            width: width,
+            height: height,
+            cells: vec![Cell::default(); width as usize * height as usize],
+            cursor: None,
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    fn index(&self, x: u16, y: u16) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(y as usize * self.width as usize + x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Place the terminal cursor at `(x, y)` once the frame is displayed.
+    pub fn set_cursor(&mut self, x: u16, y: u16) {
+        self.cursor = Some((x, y));
+    }
+
+    pub fn hide_cursor(&mut self) {
+        self.cursor = None;
+    }
+
+    /// Blank the cells of `rect`.
+    pub fn clear(&mut self, rect: Rect) {
+        for y in rect.y..rect.y.saturating_add(rect.height) {
+            for x in rect.x..rect.x.saturating_add(rect.width) {
+                self.set(x, y, " ", 1, Attributes::default());
+            }
+        }
+    }
+
+    pub fn clear_all(&mut self) {
+        for cell in &mut self.cells {
+            *cell = Cell::default();
+        }
+    }
+
+    /// Draw `text` from `(x, y)`, clipped at the right edge of the grid, and return the number of
+    /// columns drawn. Tabs are expanded and control characters are replaced, like in a line of the
+    /// buffer.
+    pub fn put_str(&mut self, x: u16, y: u16, text: &str, attributes: Attributes) -> u16 {
+        let layout = LineLayout {
+            tab_width: DEFAULT_TAB_WIDTH,
+            show_tabs: false,
+            first_column: 0,
+            width: self.width.saturating_sub(x),
+        };
+        let mut printable = String::new();
+        width::push_printable(&mut printable, text, &mut 0, &layout);
+        let mut column = x;
+        for grapheme in printable.graphemes(true) {
+            let width = width::grapheme_width(grapheme, 0, DEFAULT_TAB_WIDTH);
+            if width == 0 {
+                // A zero-width grapheme, like a zero-width space, is displayed with the character
+                // before it. There is none at the start of the text, since the cell before may
+                // belong to something else.
+                if column > x {
+                    self.append(column - 1, y, grapheme);
+                }
+                continue;
+            }
+            self.set(column, y, grapheme, width, attributes);
+            column += width;
+        }
+        column - x
+    }
+
+    /// Append `grapheme` to the symbol of the cell `(x, y)`, or of the wide character that covers
+    /// it.
+    fn append(&mut self, x: u16, y: u16, grapheme: &str) {
+        let mut index = match self.index(x, y) {
+            Some(index) => index,
+            None => return,
+        };
+        if self.cells[index].width == 0 {
+            index -= 1;
+        }
+        self.cells[index].symbol.push_str(grapheme);
+    }
+
+    /// Set the cell `(x, y)`. A wide character that doesn't fit in the row is replaced by a space.
+    fn set(&mut self, x: u16, y: u16, symbol: &str, width: u16, attributes: Attributes) {
+        let index = match self.index(x, y) {
+            Some(index) => index,
+            None => return,
+        };
+        let (symbol, width) = if width == 2 && x + 1 >= self.width {
+            (" ", 1)
+        } else {
+            (symbol, width)
+        };
+        self.break_wide(index);
+        if width == 2 {
+            self.break_wide(index + 1);
+        }
+        self.cells[index] = Cell {
+            symbol: symbol.to_owned(),
+            width: width,
+            attributes: attributes,
+        };
+        if width == 2 {
+            self.cells[index + 1] = Cell {
+                symbol: String::new(),
+                width: 0,
+                attributes: attributes,
+            };
+        }
+    }
+
+    /// If the cell at `index` is covered by a wide character, which is about to be partly
+    /// overwritten, replace the other half of the character by a space.
+    fn break_wide(&mut self, index: usize) {
+        let other = match self.cells[index].width {
+            0 => index - 1,
+            2 => index + 1,
+            _ => return,
+        };
+        let attributes = self.cells[other].attributes;
+        self.cells[other] = Cell {
+            attributes: attributes,
+            ..Cell::default()
+        };
+    }
+
+    /// Return what must be written to the terminal to turn it from displaying `previous` to
+    /// displaying this grid. If `previous` is `None`, or doesn't have the size of this grid, the
+    /// terminal is cleared and the whole grid is written.
+    pub fn diff(&self, previous: Option<&Grid>, colors: ColorSupport) -> String {
+        let blank;
+        let mut output = format!("{}", cursor::Hide);
+        let previous = match previous {
+            Some(previous) if (previous.width, previous.height) == (self.width, self.height) => {
+                previous
+            }
+            _ => {
+                output.push_str(&format!("{}", clear::All));
+                blank = Grid::new(self.width, self.height);
+                &blank
+            }
+        };
+
+        // Position of the terminal cursor while the cells are written, if known. Writing in the
+        // last column of a row may or may not move the cursor to the next row, depending on the
+        // terminal.
+        let mut position = None;
+        let mut current = Attributes::default();
+        for (index, (cell, old)) in self.cells.iter().zip(&previous.cells).enumerate() {
+            // The right half of a wide character is written with its left half.
+            if cell.width == 0 || cell == old {
+                continue;
+            }
+            let x = (index % self.width as usize) as u16;
+            let y = (index / self.width as usize) as u16;
+            if position != Some((x, y)) {
+                output.push_str(&format!("{}", cursor::Goto(x + 1, y + 1)));
+            }
+            output.push_str(&cell.attributes.transition(&current, colors));
+            output.push_str(&cell.symbol);
+            current = cell.attributes;
+            let next = x + cell.width;
+            position = if next < self.width {
+                Some((next, y))
+            } else {
+                None
+            };
+        }
+        output.push_str(&Attributes::default().transition(&current, colors));
+
+        if let Some((x, y)) = self.cursor {
+            output.push_str(&format!("{}{}", cursor::Goto(x + 1, y + 1), cursor::Show));
+        }
+        output
+    }
+}
+
+#[test]
+fn wide_characters() {
+    let mut grid = Grid::new(4, 1);
+    assert_eq!(grid.put_str(0, 0, "a漢字", Attributes::default()), 4);
+    let symbols = |grid: &Grid| -> Vec<String> {
+        grid.cells.iter().map(|cell| cell.symbol.clone()).collect()
+    };
+    // The last character doesn't fit, so it's replaced by a space.
+    assert_eq!(symbols(&grid), vec!["a", "漢", "", " "]);
+    // Overwriting half of a wide character erases the other half.
+    grid.put_str(2, 0, "b", Attributes::default());
+    assert_eq!(symbols(&grid), vec!["a", " ", "b", " "]);
+}
+
+#[test]
+fn zero_width_graphemes() {
+    let mut grid = Grid::new(4, 1);
+    assert_eq!(grid.put_str(0, 0, "x\u{200b}a", Attributes::default()), 2);
+    let symbols: Vec<&str> = grid.cells.iter().map(|cell| cell.symbol.as_str()).collect();
+    assert_eq!(symbols, vec!["x\u{200b}", "a", " ", " "]);
+    assert!(grid.cells.iter().all(|cell| cell.width == 1));
+}
+
+#[test]
+fn diff_changed_cells() {
+    let colors = ColorSupport::TrueColor;
+    let mut previous = Grid::new(5, 2);
+    previous.put_str(0, 0, "hello", Attributes::default());
+    let mut grid = previous.clone();
+    grid.put_str(1, 0, "a", Attributes::default());
+    let invert = Attributes {
+        invert: true,
+        ..Default::default()
+    };
+    grid.put_str(2, 1, "xy", invert);
+    grid.set_cursor(0, 1);
+
+    assert_eq!(
+        grid.diff(Some(&previous), colors),
+        format!(
+            "{}{}a{}{}xy{}{}{}",
+            cursor::Hide,
+            cursor::Goto(2, 1),
+            cursor::Goto(3, 2),
+            ::termion::style::Invert,
+            ::termion::style::Reset,
+            cursor::Goto(1, 2),
+            cursor::Show
+        )
+    );
+    // Nothing changed, and the cursor is hidden.
+    assert_eq!(previous.diff(Some(&previous), colors), format!("{}", cursor::Hide));
+}
@@ -1,5 +1,6 @@
-use std;
+use std::fs;
 use std::io::stdin;
+use std::path::Path;
 use std::sync::mpsc;
 use std::thread;
 
@@ -9,12 +10,34 @@ use termion::event::MouseButton;
 use termion::event::MouseEvent;
 use termion::input::TermRead;
 
+use commands;
 use core::Core;
 use errors::*;
+use keymap;
+use keymap::{Command, Keymap, Lookup, Trigger};
+use layout::Direction;
+use prompt::{PromptEvent, PromptKind};
+use screen::Screen;
+
+/// Escape sequences that the terminal sends around pasted text, in bracketed paste mode. termion
+/// doesn't know them, so they are reported as unsupported events.
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Share of its split that a pane grows or shrinks by, in percent.
+const PANE_RESIZE_STEP: i16 = 5;
+
+/// What the input thread reads from the terminal.
+#[derive(Debug, PartialEq)]
+pub enum UserInput {
+    Event(Event),
+    /// Text pasted in the terminal, received as a whole.
+    Paste(String),
+}
 
 pub struct Input {
-    tx: mpsc::Sender<Event>,
-    rx: mpsc::Receiver<Event>,
+    tx: mpsc::Sender<UserInput>,
+    rx: mpsc::Receiver<UserInput>,
 }
 
 impl Input {
@@ -27,92 +50,374 @@ impl Input {
         let tx = self.tx.clone();
         thread::spawn(move || {
             info!("waiting for input events");
+            // Text pasted so far, while between the paste markers.
+            let mut paste: Option<String> = None;
             for event_res in stdin().events() {
-                match event_res {
-                    Ok(event) => {
-                        tx.send(event).unwrap();
-                    }
+                let event = match event_res {
+                    Ok(event) => event,
                     Err(err) => {
                         error!("{:?}", err);
+                        continue;
                     }
-                }
+                };
+                let input = match (event, paste.take()) {
+                    (Event::Unsupported(ref bytes), None) if bytes.as_slice() == PASTE_START => {
+                        paste = Some(String::new());
+                        continue;
+                    }
+                    (Event::Unsupported(ref bytes), Some(text))
+                        if bytes.as_slice() == PASTE_END =>
+                    {
+                        UserInput::Paste(text)
+                    }
+                    (event, Some(mut text)) => {
+                        if let Event::Key(key) = event {
+                            push_pasted_key(&mut text, key);
+                        }
+                        paste = Some(text);
+                        continue;
+                    }
+                    (event, None) => UserInput::Event(event),
+                };
+                tx.send(input).unwrap();
             }
             info!("stop waiting for input events");
         });
     }
 
-    pub fn try_recv(&mut self) -> ::std::result::Result<Event, mpsc::TryRecvError> {
+    pub fn try_recv(&mut self) -> ::std::result::Result<UserInput, mpsc::TryRecvError> {
         self.rx.try_recv()
     }
 }
 
-pub fn handle(event: &Event, core: &mut Core) -> Result<()> {
-    match *event {
-        Event::Key(key) => match key {
-            Key::Char(c) => {
-                core.char(c)?;
+/// Append the character that produced `key` to the pasted text. termion decodes the raw input
+/// into keys, so control characters have to be converted back.
+fn push_pasted_key(text: &mut String, key: Key) {
+    match key {
+        Key::Char(c) => text.push(c),
+        Key::Ctrl(c @ 'a'..='z') => text.push((c as u8 - b'a' + 1) as char),
+        Key::Ctrl(c @ '4'..='7') => text.push((c as u8 - b'4' + 0x1c) as char),
+        Key::Alt(c) => {
+            text.push('\x1b');
+            text.push(c);
+        }
+        Key::Esc => text.push('\x1b'),
+        Key::Backspace => text.push('\x7f'),
+        _ => error!("ignoring pasted key {:?}", key),
+    }
+}
+
+pub fn handle(
+    input: &UserInput,
+    keymap: &mut Keymap,
+    core: &mut Core,
+    screen: &mut Screen,
+) -> Result<()> {
+    // The pasted text can only be replaced right after it was pasted, so any input forgets it.
+    let just_pasted = core.clipboard.take_just_pasted();
+    match *input {
+        UserInput::Event(ref event) => handle_event(event, just_pasted, keymap, core, screen),
+        UserInput::Paste(ref text) => {
+            if core.crash_report().is_some() {
+                return Ok(());
             }
-            Key::Ctrl(c) => match c {
-                'c' => {
-                    info!("received ^C: exiting");
-                    std::process::exit(0);
-                }
-                'w' => {
-                    info!("received ^W: writing current file");
-                    core.save()?;
-                }
-                _ => {
-                    bail!(ErrorKind::InputError);
-                }
-            },
-            Key::Backspace => {
-                core.del()?;
+            if screen.prompt.is_active() {
+                screen.prompt.insert(text);
+                screen.schedule_update();
+                return Ok(());
             }
-            Key::Left => {
-                core.left()?;
+            // The core inserts the whole text at once, without auto-indenting each line.
+            core.paste(text.clone())
+        }
+    }
+}
+
+fn handle_event(
+    event: &Event,
+    just_pasted: bool,
+    keymap: &mut Keymap,
+    core: &mut Core,
+    screen: &mut Screen,
+) -> Result<()> {
+    if core.crash_report().is_some() {
+        return handle_crashed(event, core, screen);
+    }
+    if screen.prompt.is_active() {
+        return match *event {
+            Event::Key(key) => {
+                screen.clear_message();
+                handle_prompt(key, core, screen)
             }
-            Key::Right => {
-                core.right()?;
+            _ => Ok(()),
+        };
+    }
+    let trigger = match *event {
+        Event::Key(key) => Trigger::Key(key),
+        Event::Mouse(mouse_event) => match mouse_event {
+            // Clicks and drags need the mouse position, so they can't be rebound.
+            MouseEvent::Press(MouseButton::Left, y, x) => return core.click(x - 1, y - 1),
+            MouseEvent::Hold(y, x) => return core.drag(x - 1, y - 1),
+            MouseEvent::Press(button, _, _) => Trigger::Mouse(button),
+            MouseEvent::Release(..) => return Ok(()),
+        },
+        Event::Unsupported(ref bytes) => match keymap::decode_sequence(bytes) {
+            Some(trigger) => trigger,
+            None => {
+                error!("unsupported event {:?}", bytes);
+                bail!(ErrorKind::InputError);
             }
-            Key::Up => {
-                core.up()?;
+        },
+    };
+    match keymap.lookup(trigger) {
+        Lookup::Command(Command::PastePrevious) if !just_pasted => Ok(()),
+        // The message in the status bar stays until the next command, so that mouse moves and
+        // the first keys of a chord don't erase an error before it can be read.
+        Lookup::Command(command) => {
+            screen.clear_message();
+            execute(command, core, screen)
+        }
+        Lookup::Pending => Ok(()),
+        // Characters that are not bound to a command are inserted.
+        Lookup::Unbound(ref triggers) if triggers.len() == 1 => match triggers[0] {
+            Trigger::Key(Key::Char(c)) => {
+                screen.clear_message();
+                core.char(c)
             }
-            Key::Down => {
-                core.down()?;
+            Trigger::Mouse(_) => Ok(()),
+            _ => {
+                error!("unbound key {:?}", triggers[0]);
+                bail!(ErrorKind::UndefinedKey(keymap::chord_name(triggers)));
             }
-            Key::PageUp => {
-                core.page_up()?;
+        },
+        Lookup::Unbound(triggers) => {
+            error!("unbound key sequence {:?}", triggers);
+            bail!(ErrorKind::UndefinedKey(keymap::chord_name(&triggers)));
+        }
+    }
+}
+
+/// Handle a key typed in the prompt.
+fn handle_prompt(key: Key, core: &mut Core, screen: &mut Screen) -> Result<()> {
+    screen.schedule_update();
+    let kind = screen.prompt.kind();
+    match (kind, screen.prompt.handle_key(key)) {
+        (PromptKind::Command, PromptEvent::Complete) => {
+            let (start, candidates) = commands::complete(screen.prompt.text(), core);
+            screen.prompt.complete(start, &candidates);
+        }
+        (PromptKind::Command, PromptEvent::Submit(line)) => commands::run(&line, core, screen)?,
+
+        // Searches are incremental: the first match is selected as the query is typed.
+        (PromptKind::Find, PromptEvent::Edited) | (PromptKind::Replace, PromptEvent::Edited) => {
+            core.find(screen.prompt.text(), screen.prompt.find_options)?;
+            core.find_next(true)?;
+        }
+        (PromptKind::Replace, PromptEvent::Submit(_)) => {
+            screen.prompt.open(PromptKind::ReplaceWith);
+        }
+        (PromptKind::ReplaceWith, PromptEvent::Submit(replacement)) => {
+            core.replace(&replacement)?;
+            screen.prompt.open(PromptKind::ConfirmReplace);
+        }
+        (PromptKind::ConfirmReplace, PromptEvent::Submit(answer)) => match answer.as_str() {
+            "y" => core.replace_next()?,
+            "n" => core.find_next(false)?,
+            "a" => {
+                core.replace_all()?;
+                screen.prompt.close();
+                core.highlight_find(false)?;
             }
-            Key::PageDown => {
-                core.page_down()?;
-            }
-            _ => {
-                error!("unsupported key event");
-                bail!(ErrorKind::InputError);
+            "q" => {
+                screen.prompt.close();
+                core.highlight_find(false)?;
             }
+            _ => {}
         },
-        Event::Mouse(mouse_event) => match mouse_event {
-            MouseEvent::Press(press_event, y, x) => match press_event {
-                MouseButton::Left => {
-                    core.click(u64::from(x) - 1, u64::from(y) - 1)?;
-                }
-                MouseButton::WheelUp => {
-                    core.up()?;
-                }
-                MouseButton::WheelDown => {
-                    core.down()?;
+        (PromptKind::SaveAs, PromptEvent::Complete) => {
+            let text = screen.prompt.text().to_owned();
+            screen.prompt.complete(0, &commands::complete_path(&text));
+        }
+        (PromptKind::SaveAs, PromptEvent::Submit(path)) => {
+            if !path.trim().is_empty() {
+                save_as(path.trim(), core, screen)?;
+            }
+        }
+        (PromptKind::CreateDirectory, PromptEvent::Submit(answer)) => {
+            let path = match answer.as_str() {
+                "y" | "n" => screen.prompt.save_path.take(),
+                _ => return Ok(()),
+            };
+            screen.prompt.close();
+            if let (Some(path), "y") = (path, answer.as_str()) {
+                if let Some(directory) = Path::new(&path).parent() {
+                    info!("creating directory {}", directory.display());
+                    fs::create_dir_all(directory)
+                        .chain_err(|| ErrorKind::SaveError(path.clone()))?;
                 }
-                _ => {}
-            },
-            MouseEvent::Release(..) => {}
-            MouseEvent::Hold(y, x) => {
-                core.drag(u64::from(x) - 1, u64::from(y) - 1)?;
+                core.save_as(&path)?;
             }
-        },
-        _ => {
-            error!("unsupported event");
-            bail!(ErrorKind::InputError);
         }
+        (PromptKind::CreateDirectory, PromptEvent::Cancel) => screen.prompt.save_path = None,
+        (PromptKind::Find, PromptEvent::Cancel) |
+        (PromptKind::Replace, PromptEvent::Cancel) |
+        (PromptKind::ReplaceWith, PromptEvent::Cancel) |
+        (PromptKind::ConfirmReplace, PromptEvent::Cancel) => core.highlight_find(false)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Run a command bound to a key.
+pub fn execute(command: Command, core: &mut Core, screen: &mut Screen) -> Result<()> {
+    info!("executing command {:?}", command);
+    match command {
+        Command::Quit => {
+            info!("exiting");
+            screen.quit();
+        }
+        // Scratch buffers don't have a file yet, so ask for one.
+        Command::Save if core.get_view().map_or(false, |view| view.filepath.is_none()) => {
+            execute(Command::SaveAs, core, screen)?
+        }
+        Command::Save => core.save()?,
+        Command::SaveAs => {
+            let filepath = core.get_view().and_then(|view| view.filepath.clone());
+            screen.prompt.open(PromptKind::SaveAs);
+            screen.prompt.insert(&filepath.unwrap_or_default());
+            screen.schedule_update();
+        }
+        Command::NextBuffer => {
+            core.next_view()?;
+            screen.schedule_update();
+        }
+        Command::PrevBuffer => {
+            core.prev_view()?;
+            screen.schedule_update();
+        }
+        Command::ToggleBufferList => screen.toggle_buffer_list(),
+        Command::HideBufferList => screen.hide_buffer_list(),
+        Command::CommandPrompt => {
+            screen.prompt.open(PromptKind::Command);
+            screen.schedule_update();
+        }
+        Command::Find | Command::Replace => {
+            let kind = if command == Command::Find {
+                PromptKind::Find
+            } else {
+                PromptKind::Replace
+            };
+            screen.prompt.open(kind);
+            core.highlight_find(true)?;
+            screen.schedule_update();
+        }
+        Command::FindNext => core.find_next(false)?,
+        Command::FindPrevious => core.find_previous()?,
+        Command::CloseBuffer => {
+            if !core.close_view()? {
+                info!("no buffer left: exiting");
+                screen.quit();
+            }
+            screen.schedule_update();
+        }
+        Command::ToggleWrap => {
+            core.update_settings(|settings| settings.wrap = !settings.wrap);
+            screen.schedule_update();
+        }
+        Command::CycleLineNumbers => {
+            core.update_settings(|settings| {
+                settings.line_numbers = settings.line_numbers.next()
+            });
+            screen.schedule_update();
+        }
+        Command::Left => core.left()?,
+        Command::Right => core.right()?,
+        Command::Up => core.up()?,
+        Command::Down => core.down()?,
+        Command::LeftSel => core.left_sel()?,
+        Command::RightSel => core.right_sel()?,
+        Command::UpSel => core.up_sel()?,
+        Command::DownSel => core.down_sel()?,
+        Command::PageUp => core.page_up()?,
+        Command::PageDown => core.page_down()?,
+        Command::PageUpSel => core.page_up_sel()?,
+        Command::PageDownSel => core.page_down_sel()?,
+        Command::LineStart => core.line_start()?,
+        Command::LineEnd => core.line_end()?,
+        Command::WordLeft => core.word_left()?,
+        Command::WordRight => core.word_right()?,
+        Command::DocumentStart => core.document_start()?,
+        Command::DocumentEnd => core.document_end()?,
+        Command::DeleteBackward => core.del()?,
+        Command::DeleteForward => core.delete_forward()?,
+        Command::InsertNewline => core.insert_newline()?,
+        Command::Undo => core.undo()?,
+        Command::Redo => core.redo()?,
+        Command::AddCursorAbove => core.add_cursor_above()?,
+        Command::AddCursorBelow => core.add_cursor_below()?,
+        Command::AddNextOccurrence => core.add_next_occurrence()?,
+        Command::CollapseCursors => core.collapse_cursors()?,
+        Command::SplitRight => core.split(Direction::Horizontal)?,
+        Command::SplitDown => core.split(Direction::Vertical)?,
+        Command::ClosePane => core.close_pane()?,
+        Command::NextPane => core.next_pane()?,
+        Command::PrevPane => core.prev_pane()?,
+        Command::GrowPane => core.resize_pane(PANE_RESIZE_STEP)?,
+        Command::ShrinkPane => core.resize_pane(-PANE_RESIZE_STEP)?,
+        Command::Copy => core.copy(|core, text| core.clipboard.copy(text?))?,
+        Command::Cut => core.cut(|core, text| core.clipboard.copy(text?))?,
+        Command::Paste => {
+            if let Some(text) = core.clipboard.paste() {
+                core.paste(text)?;
+            }
+        }
+        Command::PastePrevious => {
+            // The pasted text is a single edit, so undoing it removes it entirely.
+            if let Some(text) = core.clipboard.paste_previous() {
+                core.undo()?;
+                core.paste(text)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Save the current buffer to `path`. If the directory of `path` doesn't exist, ask whether to
+/// create it first.
+pub fn save_as(path: &str, core: &mut Core, screen: &mut Screen) -> Result<()> {
+    let missing_directory = Path::new(path)
+        .parent()
+        .map_or(false, |dir| !dir.as_os_str().is_empty() && !dir.exists());
+    if missing_directory {
+        screen.prompt.save_path = Some(path.to_owned());
+        screen.prompt.open(PromptKind::CreateDirectory);
+        screen.schedule_update();
+        return Ok(());
+    }
+    core.save_as(path)
+}
+
+/// Handle input while the core is not running: the only thing we can do is restart it or exit.
+fn handle_crashed(event: &Event, core: &mut Core, screen: &mut Screen) -> Result<()> {
+    match *event {
+        Event::Key(Key::Char('r')) => {
+            info!("restarting the core");
+            core.restart()?;
+            screen.schedule_update();
+        }
+        Event::Key(Key::Ctrl('c')) => {
+            info!("received ^C: exiting");
+            screen.quit();
+        }
+        _ => {}
     }
     Ok(())
 }
+
+#[test]
+fn pasted_keys() {
+    let mut text = String::new();
+    for key in &[Key::Char('a'), Key::Char('\t'), Key::Ctrl('a'), Key::Char('\n'), Key::Up] {
+        push_pasted_key(&mut text, *key);
+    }
+    assert_eq!(text, "a\t\x01\n");
+}
@@ -0,0 +1,621 @@
+//! Mapping of keys and mouse buttons to editor commands.
+//!
+//! The default bindings can be overridden by a JSON file that maps key sequences to command
+//! names, for instance:
+//!
+//! ```json
+//! {
+//!     "Ctrl-s": "save",
+//!     "Ctrl-x Ctrl-f": "toggle_buffer_list",
+//!     "Ctrl-q": null
+//! }
+//! ```
+//!
+//! A `null` command removes the binding.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+use termion::event::{Key, MouseButton};
+
+use errors::*;
+
+/// The commands that can be bound to a key.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+    Quit,
+    Save,
+    /// Open a prompt to save the buffer to another file
+    SaveAs,
+    NextBuffer,
+    PrevBuffer,
+    ToggleBufferList,
+    HideBufferList,
+    CloseBuffer,
+    /// Open the command prompt
+    CommandPrompt,
+    /// Open the search prompt
+    Find,
+    FindNext,
+    FindPrevious,
+    /// Open the search prompt to replace the matches
+    Replace,
+    ToggleWrap,
+    CycleLineNumbers,
+    Left,
+    Right,
+    Up,
+    Down,
+    LeftSel,
+    RightSel,
+    UpSel,
+    DownSel,
+    PageUp,
+    PageDown,
+    PageUpSel,
+    PageDownSel,
+    LineStart,
+    LineEnd,
+    WordLeft,
+    WordRight,
+    DocumentStart,
+    DocumentEnd,
+    DeleteBackward,
+    DeleteForward,
+    InsertNewline,
+    Undo,
+    Redo,
+    /// Add a cursor on the line above the topmost cursor
+    AddCursorAbove,
+    /// Add a cursor on the line below the bottommost cursor
+    AddCursorBelow,
+    /// Select the next occurrence of the selection (or of the word under the cursor), in addition
+    /// to the current selections
+    AddNextOccurrence,
+    /// Go back to a single cursor
+    CollapseCursors,
+    /// Split the pane in two, side by side
+    SplitRight,
+    /// Split the pane in two, one above the other
+    SplitDown,
+    ClosePane,
+    NextPane,
+    PrevPane,
+    GrowPane,
+    ShrinkPane,
+    Copy,
+    Cut,
+    Paste,
+    /// Replace the text that was just pasted by the previous entry of the kill ring
+    PastePrevious,
+}
+
+/// Modifiers held with a special key, like an arrow. Termion only reports the modifiers of
+/// characters (`Key::Ctrl` and `Key::Alt`), so we decode the others from the escape sequences it
+/// doesn't support.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+/// A key or a mouse button that can be part of a binding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Trigger {
+    Key(Key),
+    /// A special key with modifiers. The key is never a character.
+    Modified(Key, Modifiers),
+    Mouse(MouseButton),
+}
+
+/// Result of looking up a trigger in the keymap.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lookup {
+    Command(Command),
+    /// The trigger is the beginning of a chord: more keys are expected.
+    Pending,
+    /// The sequence of triggers is not bound to anything.
+    Unbound(Vec<Trigger>),
+}
+
+pub struct Keymap {
+    bindings: HashMap<Vec<Trigger>, Command>,
+    /// Triggers received so far for the current chord.
+    pending: Vec<Trigger>,
+}
+
+impl Keymap {
+    /// Return a keymap with the default bindings.
+    pub fn new() -> Keymap {
+        use self::Command::*;
+        use self::Trigger::Mouse;
+
+        let key = |key| vec![Trigger::Key(key)];
+        let shift = |key| {
+            let modifiers = Modifiers {
+                shift: true,
+                ..Default::default()
+            };
+            vec![Trigger::Modified(key, modifiers)]
+        };
+        let ctrl = |key| {
+            let modifiers = Modifiers {
+                ctrl: true,
+                ..Default::default()
+            };
+            vec![Trigger::Modified(key, modifiers)]
+        };
+        let alt = |key| {
+            let modifiers = Modifiers {
+                alt: true,
+                ..Default::default()
+            };
+            vec![Trigger::Modified(key, modifiers)]
+        };
+        let bindings = vec![
+            (key(Key::Ctrl('c')), Quit),
+            (key(Key::Ctrl('w')), Save),
+            (key(Key::Ctrl('n')), NextBuffer),
+            (key(Key::Ctrl('p')), PrevBuffer),
+            (key(Key::Ctrl('b')), ToggleBufferList),
+            (key(Key::Ctrl('q')), CloseBuffer),
+            (key(Key::Esc), HideBufferList),
+            (key(Key::Alt('x')), CommandPrompt),
+            (key(Key::Alt(':')), CommandPrompt),
+            (key(Key::Ctrl('f')), Find),
+            (key(Key::F(3)), FindNext),
+            (key(Key::Alt('n')), FindNext),
+            (key(Key::Alt('p')), FindPrevious),
+            (key(Key::Alt('%')), Replace),
+            (key(Key::F(1)), ToggleWrap),
+            (key(Key::F(2)), CycleLineNumbers),
+            (key(Key::Left), Left),
+            (key(Key::Right), Right),
+            (key(Key::Up), Up),
+            (key(Key::Down), Down),
+            (key(Key::PageUp), PageUp),
+            (key(Key::PageDown), PageDown),
+            (shift(Key::Left), LeftSel),
+            (shift(Key::Right), RightSel),
+            (shift(Key::Up), UpSel),
+            (shift(Key::Down), DownSel),
+            (shift(Key::PageUp), PageUpSel),
+            (shift(Key::PageDown), PageDownSel),
+            (key(Key::Home), LineStart),
+            (key(Key::End), LineEnd),
+            (ctrl(Key::Left), WordLeft),
+            (ctrl(Key::Right), WordRight),
+            (ctrl(Key::Home), DocumentStart),
+            (ctrl(Key::End), DocumentEnd),
+            (key(Key::Backspace), DeleteBackward),
+            (key(Key::Delete), DeleteForward),
+            (key(Key::Ctrl('z')), Undo),
+            (key(Key::Ctrl('r')), Redo),
+            (alt(Key::Up), AddCursorAbove),
+            (alt(Key::Down), AddCursorBelow),
+            (key(Key::Ctrl('d')), AddNextOccurrence),
+            (key(Key::Ctrl('g')), CollapseCursors),
+            (key(Key::Alt('w')), Copy),
+            (key(Key::Ctrl('k')), Cut),
+            (key(Key::Ctrl('y')), Paste),
+            (key(Key::Alt('y')), PastePrevious),
+            (
+                vec![Trigger::Key(Key::Ctrl('x')), Trigger::Key(Key::Ctrl('s'))],
+                Save,
+            ),
+            (
+                vec![Trigger::Key(Key::Ctrl('x')), Trigger::Key(Key::Ctrl('w'))],
+                SaveAs,
+            ),
+            (
+                vec![Trigger::Key(Key::Ctrl('x')), Trigger::Key(Key::Ctrl('c'))],
+                Quit,
+            ),
+            (
+                vec![Trigger::Key(Key::Ctrl('x')), Trigger::Key(Key::Char('3'))],
+                SplitRight,
+            ),
+            (
+                vec![Trigger::Key(Key::Ctrl('x')), Trigger::Key(Key::Char('2'))],
+                SplitDown,
+            ),
+            (
+                vec![Trigger::Key(Key::Ctrl('x')), Trigger::Key(Key::Char('0'))],
+                ClosePane,
+            ),
+            (
+                vec![Trigger::Key(Key::Ctrl('x')), Trigger::Key(Key::Char('o'))],
+                NextPane,
+            ),
+            (
+                vec![Trigger::Key(Key::Ctrl('x')), Trigger::Key(Key::Char('p'))],
+                PrevPane,
+            ),
+            (
+                vec![Trigger::Key(Key::Ctrl('x')), Trigger::Key(Key::Char('+'))],
+                GrowPane,
+            ),
+            (
+                vec![Trigger::Key(Key::Ctrl('x')), Trigger::Key(Key::Char('-'))],
+                ShrinkPane,
+            ),
+            (vec![Mouse(MouseButton::WheelUp)], Up),
+            (vec![Mouse(MouseButton::WheelDown)], Down),
+        ];
+        Keymap {
+            bindings: bindings.into_iter().collect(),
+            pending: vec![],
+        }
+    }
+
+    /// Return a keymap with the default bindings, overridden by the bindings of the JSON file at
+    /// `path`.
+    pub fn load(path: &Path) -> Result<Keymap> {
+        let config_error = || ErrorKind::ConfigError(path.display().to_string());
+        let file = File::open(path).chain_err(&config_error)?;
+        let overrides: HashMap<String, Option<Command>> =
+            serde_json::from_reader(file).chain_err(&config_error)?;
+
+        let mut keymap = Keymap::new();
+        for (chord, command) in overrides {
+            let chord = parse_chord(&chord).chain_err(&config_error)?;
+            match command {
+                Some(command) => keymap.bindings.insert(chord, command),
+                None => keymap.bindings.remove(&chord),
+            };
+        }
+        Ok(keymap)
+    }
+
+    /// Return the path of the keymap file in the user's configuration directory:
+    /// `$XDG_CONFIG_HOME/xi-tui/keymap.json`, or `~/.config/xi-tui/keymap.json`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join("xi-tui").join("keymap.json"))
+    }
+
+    /// Feed a trigger to the keymap. If it completes a binding, the bound command is returned.
+    pub fn lookup(&mut self, trigger: Trigger) -> Lookup {
+        self.pending.push(trigger);
+        if let Some(command) = self.bindings.get(&self.pending) {
+            self.pending.clear();
+            return Lookup::Command(*command);
+        }
+        let pending = &self.pending;
+        if self.bindings
+            .keys()
+            .any(|chord| chord.len() > pending.len() && chord.starts_with(pending))
+        {
+            return Lookup::Pending;
+        }
+        Lookup::Unbound(self.pending.drain(..).collect())
+    }
+}
+
+/// Parse a sequence of triggers separated by spaces, like `Ctrl-x Ctrl-s`.
+fn parse_chord(chord: &str) -> Result<Vec<Trigger>> {
+    let triggers = chord
+        .split_whitespace()
+        .map(parse_trigger)
+        .collect::<Result<Vec<Trigger>>>()?;
+    if triggers.is_empty() {
+        bail!(ErrorKind::InvalidKey(chord.into()));
+    }
+    Ok(triggers)
+}
+
+/// Parse the name of a key, like `Ctrl-a`, `Alt-x`, `PageUp`, `Ctrl-Shift-Left`, `F5` or `x`, or
+/// of a mouse button, like `WheelUp`.
+fn parse_trigger(name: &str) -> Result<Trigger> {
+    // Special keys can have any combination of modifiers
+    let mut modifiers = Modifiers::default();
+    let mut key_name = name;
+    loop {
+        if key_name.starts_with("Shift-") {
+            modifiers.shift = true;
+            key_name = &key_name[6..];
+        } else if key_name.starts_with("Ctrl-") && key_name.chars().count() > 6 {
+            modifiers.ctrl = true;
+            key_name = &key_name[5..];
+        } else if key_name.starts_with("Alt-") && key_name.chars().count() > 5 {
+            modifiers.alt = true;
+            key_name = &key_name[4..];
+        } else {
+            break;
+        }
+    }
+    if modifiers != Modifiers::default() {
+        return match parse_trigger(key_name) {
+            Ok(Trigger::Key(key)) if is_special(key) => Ok(Trigger::Modified(key, modifiers)),
+            _ => bail!(ErrorKind::InvalidKey(name.into())),
+        };
+    }
+
+    let single_char = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+
+    let key = match name {
+        "Backspace" => Key::Backspace,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "Delete" => Key::Delete,
+        "Insert" => Key::Insert,
+        "Esc" => Key::Esc,
+        "Enter" => Key::Char('\n'),
+        "Tab" => Key::Char('\t'),
+        "Space" => Key::Char(' '),
+        "WheelUp" => return Ok(Trigger::Mouse(MouseButton::WheelUp)),
+        "WheelDown" => return Ok(Trigger::Mouse(MouseButton::WheelDown)),
+        "MouseMiddle" => return Ok(Trigger::Mouse(MouseButton::Middle)),
+        "MouseRight" => return Ok(Trigger::Mouse(MouseButton::Right)),
+        _ => {
+            let key = if name.starts_with("Ctrl-") {
+                single_char(&name[5..]).map(Key::Ctrl)
+            } else if name.starts_with("Alt-") {
+                single_char(&name[4..]).map(Key::Alt)
+            } else if name.starts_with('F') && name.len() > 1 {
+                name[1..]
+                    .parse()
+                    .ok()
+                    .and_then(|n| if n >= 1 && n <= 12 { Some(Key::F(n)) } else { None })
+            } else {
+                single_char(name).map(Key::Char)
+            };
+            match key {
+                Some(key) => key,
+                None => bail!(ErrorKind::InvalidKey(name.into())),
+            }
+        }
+    };
+    Ok(Trigger::Key(key))
+}
+
+/// Return the name of a sequence of triggers, as `parse_chord` accepts it.
+pub fn chord_name(triggers: &[Trigger]) -> String {
+    triggers
+        .iter()
+        .map(trigger_name)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn trigger_name(trigger: &Trigger) -> String {
+    match *trigger {
+        Trigger::Key(key) => key_name(key),
+        Trigger::Modified(key, modifiers) => {
+            let mut name = String::new();
+            if modifiers.ctrl {
+                name.push_str("Ctrl-");
+            }
+            if modifiers.alt {
+                name.push_str("Alt-");
+            }
+            if modifiers.shift {
+                name.push_str("Shift-");
+            }
+            name.push_str(&key_name(key));
+            name
+        }
+        Trigger::Mouse(MouseButton::WheelUp) => "WheelUp".into(),
+        Trigger::Mouse(MouseButton::WheelDown) => "WheelDown".into(),
+        Trigger::Mouse(MouseButton::Middle) => "MouseMiddle".into(),
+        Trigger::Mouse(MouseButton::Right) => "MouseRight".into(),
+        Trigger::Mouse(MouseButton::Left) => "MouseLeft".into(),
+    }
+}
+
+fn key_name(key: Key) -> String {
+    match key {
+        Key::Char('\n') => "Enter".into(),
+        Key::Char('\t') => "Tab".into(),
+        Key::Char(' ') => "Space".into(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("Ctrl-{}", c),
+        Key::Alt(c) => format!("Alt-{}", c),
+        Key::F(n) => format!("F{}", n),
+        // The other names are the names of the variants.
+        key => format!("{:?}", key),
+    }
+}
+
+/// Return whether `key` is one of the keys that can be combined with modifiers.
+fn is_special(key: Key) -> bool {
+    match key {
+        Key::Left | Key::Right | Key::Up | Key::Down | Key::Home | Key::End | Key::PageUp |
+        Key::PageDown | Key::Delete | Key::Insert => true,
+        _ => false,
+    }
+}
+
+/// Decode an escape sequence that termion reports as `Event::Unsupported`. These are mostly
+/// special keys with modifiers, which are encoded as:
+///
+/// - `ESC [ 1 ; <modifiers> <A-D, H or F>` for the arrows, Home and End,
+/// - `ESC [ <key> ; <modifiers> ~` for the other keys,
+///
+/// where `<modifiers>` is 1 plus a bitmask of shift (1), alt (2) and ctrl (4). rxvt uses its own
+/// sequences: `ESC [ <a-d>` for shift + arrows, `ESC O <a-d>` for ctrl + arrows, and a `$` or `^`
+/// final byte instead of `~` for shift or ctrl.
+pub fn decode_sequence(bytes: &[u8]) -> Option<Trigger> {
+    let arrow = |c: u8| match c {
+        b'A' | b'a' => Some(Key::Up),
+        b'B' | b'b' => Some(Key::Down),
+        b'C' | b'c' => Some(Key::Right),
+        b'D' | b'd' => Some(Key::Left),
+        b'H' => Some(Key::Home),
+        b'F' => Some(Key::End),
+        _ => None,
+    };
+    let numbered = |n: &str| match n {
+        "1" | "7" => Some(Key::Home),
+        "2" => Some(Key::Insert),
+        "3" => Some(Key::Delete),
+        "4" | "8" => Some(Key::End),
+        "5" => Some(Key::PageUp),
+        "6" => Some(Key::PageDown),
+        _ => None,
+    };
+    let with = |key: Key, modifiers: Modifiers| {
+        if modifiers == Modifiers::default() {
+            Trigger::Key(key)
+        } else {
+            Trigger::Modified(key, modifiers)
+        }
+    };
+    let shift = Modifiers {
+        shift: true,
+        ..Default::default()
+    };
+    let ctrl = Modifiers {
+        ctrl: true,
+        ..Default::default()
+    };
+
+    let (&last, body) = bytes.split_last()?;
+    match body {
+        b"\x1b[" if last >= b'a' => return arrow(last).map(|key| with(key, shift)),
+        b"\x1bO" if last >= b'a' => return arrow(last).map(|key| with(key, ctrl)),
+        _ if !body.starts_with(b"\x1b[") => return None,
+        _ => {}
+    }
+
+    let params = ::std::str::from_utf8(&body[2..]).ok()?;
+    let mut params = params.split(';');
+    let first = params.next()?;
+    let modifiers = match params.next() {
+        Some(param) => {
+            let mask = param.parse::<u8>().ok()?.checked_sub(1)?;
+            Modifiers {
+                shift: mask & 1 != 0,
+                alt: mask & 2 != 0,
+                ctrl: mask & 4 != 0,
+            }
+        }
+        None => Modifiers::default(),
+    };
+    if params.next().is_some() {
+        return None;
+    }
+
+    match last {
+        b'~' => numbered(first).map(|key| with(key, modifiers)),
+        b'$' if modifiers == Modifiers::default() => numbered(first).map(|key| with(key, shift)),
+        b'^' if modifiers == Modifiers::default() => numbered(first).map(|key| with(key, ctrl)),
+        _ if first == "1" => arrow(last).map(|key| with(key, modifiers)),
+        _ => None,
+    }
+}
+
+#[test]
+fn parse_triggers() {
+    assert_eq!(parse_trigger("Ctrl-x").unwrap(), Trigger::Key(Key::Ctrl('x')));
+    assert_eq!(parse_trigger("Alt-w").unwrap(), Trigger::Key(Key::Alt('w')));
+    assert_eq!(parse_trigger("F12").unwrap(), Trigger::Key(Key::F(12)));
+    assert_eq!(parse_trigger("F").unwrap(), Trigger::Key(Key::Char('F')));
+    assert_eq!(parse_trigger("PageUp").unwrap(), Trigger::Key(Key::PageUp));
+    assert_eq!(
+        parse_trigger("WheelDown").unwrap(),
+        Trigger::Mouse(MouseButton::WheelDown)
+    );
+    assert_eq!(
+        parse_trigger("Ctrl-Shift-Left").unwrap(),
+        Trigger::Modified(
+            Key::Left,
+            Modifiers {
+                shift: true,
+                alt: false,
+                ctrl: true,
+            }
+        )
+    );
+    assert_eq!(parse_trigger("Alt-é").unwrap(), Trigger::Key(Key::Alt('é')));
+    assert!(parse_trigger("Shift-a").is_err());
+    assert!(parse_trigger("F13").is_err());
+    assert!(parse_trigger("Ctrl-").is_err());
+    assert!(parse_trigger("Hyper-x").is_err());
+    assert!(parse_chord(" ").is_err());
+}
+
+#[test]
+fn lookup_chords() {
+    let mut keymap = Keymap::new();
+    let ctrl = |c| Trigger::Key(Key::Ctrl(c));
+    assert_eq!(keymap.lookup(ctrl('w')), Lookup::Command(Command::Save));
+    assert_eq!(keymap.lookup(ctrl('x')), Lookup::Pending);
+    assert_eq!(keymap.lookup(ctrl('s')), Lookup::Command(Command::Save));
+    assert_eq!(keymap.lookup(ctrl('x')), Lookup::Pending);
+    assert_eq!(keymap.lookup(ctrl('w')), Lookup::Command(Command::SaveAs));
+    assert_eq!(keymap.lookup(ctrl('x')), Lookup::Pending);
+    assert_eq!(
+        keymap.lookup(Trigger::Key(Key::Char('3'))),
+        Lookup::Command(Command::SplitRight)
+    );
+    assert_eq!(keymap.lookup(ctrl('x')), Lookup::Pending);
+    let undefined = vec![ctrl('x'), Trigger::Key(Key::Char('a'))];
+    assert_eq!(
+        keymap.lookup(Trigger::Key(Key::Char('a'))),
+        Lookup::Unbound(undefined.clone())
+    );
+    assert_eq!(chord_name(&undefined), "Ctrl-x a");
+    // The failed chord is forgotten
+    assert_eq!(
+        keymap.lookup(Trigger::Key(Key::Char('a'))),
+        Lookup::Unbound(vec![Trigger::Key(Key::Char('a'))])
+    );
+}
+
+#[test]
+fn decode_modified_keys() {
+    let shift = Modifiers {
+        shift: true,
+        ..Default::default()
+    };
+    let ctrl = Modifiers {
+        ctrl: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        decode_sequence(b"\x1b[1;2D"),
+        Some(Trigger::Modified(Key::Left, shift))
+    );
+    assert_eq!(
+        decode_sequence(b"\x1b[1;5F"),
+        Some(Trigger::Modified(Key::End, ctrl))
+    );
+    assert_eq!(
+        decode_sequence(b"\x1b[5;2~"),
+        Some(Trigger::Modified(Key::PageUp, shift))
+    );
+    assert_eq!(decode_sequence(b"\x1b[1;1A"), Some(Trigger::Key(Key::Up)));
+    // rxvt
+    assert_eq!(
+        decode_sequence(b"\x1b[b"),
+        Some(Trigger::Modified(Key::Down, shift))
+    );
+    assert_eq!(
+        decode_sequence(b"\x1bOc"),
+        Some(Trigger::Modified(Key::Right, ctrl))
+    );
+    assert_eq!(decode_sequence(b"\x1b[1;2;3A"), None);
+    assert_eq!(decode_sequence(b"\x1b[42~"), None);
+    assert_eq!(decode_sequence(b"junk"), None);
+}
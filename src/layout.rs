@@ -0,0 +1,458 @@
+//! Layout of the panes the views are displayed in. The area of the views is split recursively,
+//! side by side or one above the other, and each pane displays one view. Several panes can
+//! display the same view.
+
+use view::Viewport;
+
+/// A rectangle on the screen, in cells. `x` and `y` start at 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.x && x - self.x < self.width && y >= self.y && y - self.y < self.height
+    }
+}
+
+/// How the two sides of a split are arranged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Side by side, separated by a vertical bar
+    Horizontal,
+    /// One above the other, separated by a horizontal bar
+    Vertical,
+}
+
+/// Smallest share of a split that one side can be resized to, in percent.
+const MIN_RATIO: i16 = 10;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pane {
+    pub id: usize,
+    /// ID of the view displayed in the pane, or an empty string until a view is opened.
+    pub view_id: String,
+    pub rect: Rect,
+    /// Viewport of the pane, if another pane displays the same view. The view itself holds the
+    /// viewport of one of its panes: the focused pane, or the first one if none is focused.
+    pub viewport: Option<Viewport>,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Pane(Pane),
+    Split {
+        direction: Direction,
+        /// Share of the split taken by the first side, in percent.
+        ratio: i16,
+        first: Box<Node>,
+        second: Box<Node>,
+    },
+}
+
+impl Node {
+    /// Return the panes of this node, from left to right and from top to bottom.
+    fn panes<'a>(&'a self, panes: &mut Vec<&'a Pane>) {
+        match *self {
+            Node::Pane(ref pane) => panes.push(pane),
+            Node::Split {
+                ref first,
+                ref second,
+                ..
+            } => {
+                first.panes(panes);
+                second.panes(panes);
+            }
+        }
+    }
+
+    fn pane_mut(&mut self, id: usize) -> Option<&mut Pane> {
+        match *self {
+            Node::Pane(ref mut pane) if pane.id == id => Some(pane),
+            Node::Pane(_) => None,
+            Node::Split {
+                ref mut first,
+                ref mut second,
+                ..
+            } => match first.pane_mut(id) {
+                Some(pane) => Some(pane),
+                None => second.pane_mut(id),
+            },
+        }
+    }
+
+    fn contains(&self, id: usize) -> bool {
+        let mut panes = vec![];
+        self.panes(&mut panes);
+        panes.iter().any(|pane| pane.id == id)
+    }
+
+    /// Give each pane its rectangle within `rect`, and collect the separators between the sides of
+    /// the splits.
+    fn place(&mut self, rect: Rect, separators: &mut Vec<(Rect, Direction)>) {
+        let (direction, ratio, first, second) = match *self {
+            Node::Pane(ref mut pane) => {
+                pane.rect = rect;
+                return;
+            }
+            Node::Split {
+                direction,
+                ratio,
+                ref mut first,
+                ref mut second,
+            } => (direction, ratio, first, second),
+        };
+        // One row or column is taken by the separator.
+        let size = |total: u16| {
+            let available = total.saturating_sub(1);
+            let first = (u32::from(available) * ratio as u32 / 100) as u16;
+            (first, available - first)
+        };
+        let (first_rect, separator, second_rect) = match direction {
+            Direction::Horizontal => {
+                let (first_width, second_width) = size(rect.width);
+                let separator_x = rect.x + first_width;
+                (
+                    Rect {
+                        width: first_width,
+                        ..rect
+                    },
+                    Rect {
+                        x: separator_x,
+                        width: ::std::cmp::min(1, rect.width),
+                        ..rect
+                    },
+                    Rect {
+                        x: separator_x + 1,
+                        width: second_width,
+                        ..rect
+                    },
+                )
+            }
+            Direction::Vertical => {
+                let (first_height, second_height) = size(rect.height);
+                let separator_y = rect.y + first_height;
+                (
+                    Rect {
+                        height: first_height,
+                        ..rect
+                    },
+                    Rect {
+                        y: separator_y,
+                        height: ::std::cmp::min(1, rect.height),
+                        ..rect
+                    },
+                    Rect {
+                        y: separator_y + 1,
+                        height: second_height,
+                        ..rect
+                    },
+                )
+            }
+        };
+        separators.push((separator, direction));
+        first.place(first_rect, separators);
+        second.place(second_rect, separators);
+    }
+
+    /// Replace the pane `id` by a split between it and `pane`. Return `false` if there is no such
+    /// pane.
+    fn split(&mut self, id: usize, direction: Direction, pane: Pane) -> bool {
+        match *self {
+            Node::Pane(ref current) if current.id == id => {}
+            Node::Pane(_) => return false,
+            Node::Split {
+                ref mut first,
+                ref mut second,
+                ..
+            } => {
+                return first.split(id, direction, pane.clone())
+                    || second.split(id, direction, pane);
+            }
+        }
+        let current = ::std::mem::replace(self, Node::Pane(Pane::default()));
+        *self = Node::Split {
+            direction: direction,
+            ratio: 50,
+            first: Box::new(current),
+            second: Box::new(Node::Pane(pane)),
+        };
+        true
+    }
+
+    /// Remove the pane `id`, and give its space to the other side of its split. Return the ID of
+    /// a pane that took the space, or `None` if there is no such pane.
+    fn remove(&mut self, id: usize) -> Option<usize> {
+        let sibling = match *self {
+            Node::Pane(_) => return None,
+            Node::Split {
+                ref mut first,
+                ref mut second,
+                ..
+            } => match (&**first, &**second) {
+                (&Node::Pane(ref pane), _) if pane.id == id => {
+                    ::std::mem::replace(&mut **second, Node::Pane(Pane::default()))
+                }
+                (_, &Node::Pane(ref pane)) if pane.id == id => {
+                    ::std::mem::replace(&mut **first, Node::Pane(Pane::default()))
+                }
+                _ => return first.remove(id).or_else(|| second.remove(id)),
+            },
+        };
+        *self = sibling;
+        let mut panes = vec![];
+        self.panes(&mut panes);
+        panes.first().map(|pane| pane.id)
+    }
+
+    /// Grow the pane `id` by `delta` percent of the innermost split it belongs to. Return `false`
+    /// if the pane is not in a split.
+    fn resize(&mut self, id: usize, delta: i16) -> bool {
+        match *self {
+            Node::Pane(_) => false,
+            Node::Split {
+                ref mut ratio,
+                ref mut first,
+                ref mut second,
+                ..
+            } => {
+                // Growing the second side means shrinking the first one.
+                let (side, first_delta) = if first.contains(id) {
+                    (first, delta)
+                } else if second.contains(id) {
+                    (second, -delta)
+                } else {
+                    return false;
+                };
+                if !side.resize(id, delta) {
+                    let new_ratio = ::std::cmp::min(100 - MIN_RATIO, *ratio + first_delta);
+                    *ratio = ::std::cmp::max(MIN_RATIO, new_ratio);
+                }
+                true
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Layout {
+    root: Node,
+    /// Area the panes are laid out in.
+    area: Rect,
+    focused: usize,
+    next_id: usize,
+    /// Separators between the panes, with the direction of their split.
+    separators: Vec<(Rect, Direction)>,
+}
+
+impl Layout {
+    /// Return a layout with a single pane that doesn't display any view yet.
+    pub fn new() -> Self {
+        Layout {
+            root: Node::Pane(Pane::default()),
+            area: Rect::default(),
+            focused: 0,
+            next_id: 1,
+            separators: vec![],
+        }
+    }
+
+    pub fn set_area(&mut self, area: Rect) {
+        self.area = area;
+        self.update_rects();
+    }
+
+    fn update_rects(&mut self) {
+        self.separators.clear();
+        self.root.place(self.area, &mut self.separators);
+    }
+
+    /// Return the panes, from left to right and from top to bottom.
+    pub fn panes(&self) -> Vec<&Pane> {
+        let mut panes = vec![];
+        self.root.panes(&mut panes);
+        panes
+    }
+
+    pub fn separators(&self) -> &[(Rect, Direction)] {
+        &self.separators
+    }
+
+    pub fn focused(&self) -> &Pane {
+        self.panes()
+            .into_iter()
+            .find(|pane| pane.id == self.focused)
+            .expect("the focused pane is not in the layout")
+    }
+
+    /// Display `view_id` in the focused pane.
+    pub fn set_view(&mut self, view_id: &str) {
+        if let Some(pane) = self.root.pane_mut(self.focused) {
+            pane.view_id = view_id.to_owned();
+            pane.viewport = None;
+        }
+    }
+
+    /// Replace `old_view_id` by `new_view_id` in the panes that display it.
+    pub fn replace_view(&mut self, old_view_id: &str, new_view_id: &str) {
+        let ids: Vec<usize> = self.panes()
+            .iter()
+            .filter(|pane| pane.view_id == old_view_id)
+            .map(|pane| pane.id)
+            .collect();
+        for id in ids {
+            if let Some(pane) = self.root.pane_mut(id) {
+                pane.view_id = new_view_id.to_owned();
+            }
+        }
+    }
+
+    pub fn pane_mut(&mut self, id: usize) -> Option<&mut Pane> {
+        self.root.pane_mut(id)
+    }
+
+    /// Return the first pane that displays `view_id`, if any.
+    pub fn pane_of(&self, view_id: &str) -> Option<&Pane> {
+        self.panes().into_iter().find(|pane| pane.view_id == view_id)
+    }
+
+    /// Return the pane at the position `(x, y)` on the screen, if any.
+    pub fn pane_at(&self, x: u16, y: u16) -> Option<&Pane> {
+        self.panes().into_iter().find(|pane| pane.rect.contains(x, y))
+    }
+
+    pub fn focus(&mut self, id: usize) {
+        if self.root.contains(id) {
+            self.focused = id;
+        }
+    }
+
+    /// Focus the pane `step` panes after the focused one, in the order of `panes()`.
+    pub fn focus_next(&mut self, step: usize) {
+        let ids: Vec<usize> = self.panes().iter().map(|pane| pane.id).collect();
+        let index = ids.iter().position(|id| *id == self.focused).unwrap_or(0);
+        self.focused = ids[(index + step) % ids.len()];
+    }
+
+    pub fn len(&self) -> usize {
+        self.panes().len()
+    }
+
+    /// Split the focused pane in two, display `view_id` in the new pane, and focus it.
+    pub fn split(&mut self, direction: Direction, view_id: &str) {
+        let pane = Pane {
+            id: self.next_id,
+            view_id: view_id.to_owned(),
+            rect: Rect::default(),
+            viewport: None,
+        };
+        if self.root.split(self.focused, direction, pane) {
+            self.focused = self.next_id;
+            self.next_id += 1;
+            self.update_rects();
+        }
+    }
+
+    /// Close the focused pane, unless it's the only one, and focus a pane next to it. Return
+    /// whether the pane was closed.
+    pub fn close_focused(&mut self) -> bool {
+        let id = self.focused;
+        self.close(id)
+    }
+
+    /// Close the pane `id`, unless it's the only one. If it's focused, a pane next to it gets the
+    /// focus. Return whether the pane was closed.
+    pub fn close(&mut self, id: usize) -> bool {
+        match self.root.remove(id) {
+            Some(next) => {
+                if self.focused == id {
+                    self.focused = next;
+                }
+                self.update_rects();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Grow the focused pane by `delta` percent of its split, or shrink it if `delta` is
+    /// negative.
+    pub fn resize_focused(&mut self, delta: i16) {
+        if self.root.resize(self.focused, delta) {
+            self.update_rects();
+        }
+    }
+}
+
+#[test]
+fn split_and_close_panes() {
+    let mut layout = Layout::new();
+    layout.set_area(Rect {
+        x: 0,
+        y: 0,
+        width: 81,
+        height: 24,
+    });
+    layout.set_view("view-id-1");
+    layout.split(Direction::Horizontal, "view-id-2");
+    layout.split(Direction::Vertical, "view-id-3");
+
+    let rects: Vec<(&str, Rect)> = layout
+        .panes()
+        .iter()
+        .map(|pane| (pane.view_id.as_str(), pane.rect))
+        .collect();
+    let rect = |x, y, width, height| Rect {
+        x: x,
+        y: y,
+        width: width,
+        height: height,
+    };
+    assert_eq!(
+        rects,
+        vec![
+            ("view-id-1", rect(0, 0, 40, 24)),
+            ("view-id-2", rect(41, 0, 40, 11)),
+            ("view-id-3", rect(41, 12, 40, 12)),
+        ]
+    );
+    assert_eq!(layout.focused().view_id, "view-id-3");
+    assert_eq!(layout.pane_at(41, 11), None);
+    assert_eq!(layout.pane_at(41, 12).map(|pane| pane.id), Some(2));
+
+    // Growing the bottom pane takes rows from the one above it.
+    layout.resize_focused(10);
+    assert_eq!(layout.focused().rect, rect(41, 10, 40, 14));
+
+    assert!(layout.close_focused());
+    assert_eq!(layout.focused().view_id, "view-id-2");
+    assert_eq!(layout.focused().rect, rect(41, 0, 40, 24));
+    layout.focus_next(1);
+    assert_eq!(layout.focused().view_id, "view-id-1");
+    assert!(layout.close_focused());
+    assert!(!layout.close_focused());
+    assert_eq!(layout.focused().rect, rect(0, 0, 81, 24));
+}
+
+#[test]
+fn panes_of_the_same_view() {
+    let mut layout = Layout::new();
+    layout.set_view("view-id-1");
+    layout.split(Direction::Vertical, "view-id-1");
+    layout.split(Direction::Horizontal, "view-id-2");
+    let view_ids = |layout: &Layout| -> Vec<String> {
+        layout.panes().iter().map(|pane| pane.view_id.clone()).collect()
+    };
+
+    layout.replace_view("view-id-1", "view-id-3");
+    assert_eq!(view_ids(&layout), vec!["view-id-3", "view-id-3", "view-id-2"]);
+
+    // Closing a pane that isn't focused leaves the focus where it is.
+    assert!(layout.close(0));
+    assert_eq!(view_ids(&layout), vec!["view-id-3", "view-id-2"]);
+    assert_eq!(layout.focused().id, 2);
+}
@@ -1,11 +1,11 @@
-use std::io::Write;
 use std::default::Default;
 
-use termion;
-use termion::clear;
-use termion::cursor;
-
 use errors::*;
+use grid::Grid;
+use style;
+use style::{Attributes, Palette};
+use width;
+use width::LineLayout;
 
 fn _return_true() -> bool {
     true
@@ -17,6 +17,8 @@ pub struct Line {
     #[serde(default)]
     #[serde(rename = "cursor")]
     pub cursors: Vec<u64>,
+    /// Style spans, as `[start, length, style_id]` triples. Offsets are in bytes, and `start` is
+    /// relative to the end of the previous span.
     #[serde(default)] pub styles: Vec<i64>,
     #[serde(default = "_return_true")]
     #[serde(skip_deserializing)]
@@ -42,18 +44,23 @@ impl Line {
         }
     }
 
-    pub fn render<W: Write>(&self, w: &mut W, lineno: u16) -> Result<()> {
+    /// Draw the line on row `y` of `grid`, starting at column `x`. The row must have been cleared.
+    /// The styles of the line are drawn over the `base` attributes, which fill the whole row.
+    pub fn render(
+        &self,
+        grid: &mut Grid,
+        x: u16,
+        y: u16,
+        palette: &Palette,
+        layout: &LineLayout,
+        base: Attributes,
+    ) -> Result<()> {
         let mut line = self.text.clone();
         self.trim_new_line(&mut line);
-        self.add_styles(&mut line)?;
-        write!(
-            w,
-            "{}{}{}",
-            cursor::Goto(1, lineno),
-            clear::CurrentLine,
-            line
-        ).chain_err(|| ErrorKind::DisplayError)?;
-        w.flush().chain_err(|| ErrorKind::DisplayError)?;
+        let mut column = x;
+        for (attributes, text) in self.styled_runs(&line, palette, layout, base)? {
+            column += grid.put_str(column, y, &text, attributes);
+        }
         Ok(())
     }
 
@@ -63,31 +70,188 @@ impl Line {
         }
     }
 
-    fn add_styles(&self, text: &mut String) -> Result<()> {
-        if self.styles.is_empty() {
-            return Ok(());
-        }
+    /// Return the visible part of `text`, with tabs expanded, as runs of text that have the same
+    /// attributes.
+    ///
+    /// Spans can overlap, for instance when a selection covers syntax colors. The text is cut
+    /// into runs that are covered by the same spans, and the styles of each run are combined by
+    /// order of precedence (see `style::layer`), later spans taking precedence within a layer.
+    fn styled_runs(
+        &self,
+        text: &str,
+        palette: &Palette,
+        layout: &LineLayout,
+        base: Attributes,
+    ) -> Result<Vec<(Attributes, String)>> {
         if self.styles.len() % 3 != 0 {
             error!("Invalid style array (should be a multiple of 3)");
             bail!(ErrorKind::DisplayError);
         }
-        let mut style_idx = 0;
-        loop {
-            let start = self.styles[style_idx] as usize;
-            let end = start + self.styles[style_idx + 1] as usize;
-
-            if end >= text.len() {
-                text.push_str(&format!("{}", termion::style::Reset));
-            } else {
-                text.insert_str(end, &format!("{}", termion::style::Reset));
+
+        // Spans as `(start, end, style_id)`, with absolute offsets
+        let mut spans = vec![];
+        // End of the previous span, which the start of the next span is relative to.
+        let mut prev_end = 0;
+        for span in self.styles.chunks(3) {
+            let start = prev_end + span[0];
+            let end = start + span[1];
+            prev_end = end;
+
+            let (start, end) = (char_boundary(text, start), char_boundary(text, end));
+            if end > start {
+                spans.push((start, end, span[2] as u16));
             }
-            text.insert_str(start, &format!("{}", termion::style::Invert));
+        }
+        // The sort is stable, so the order of the spans is kept within a layer.
+        spans.sort_by_key(|&(_, _, id)| style::layer(id));
+
+        // Offsets where a run starts or ends
+        let mut bounds = vec![0, text.len()];
+        for &(start, end, _) in &spans {
+            bounds.push(start);
+            bounds.push(end);
+        }
+        bounds.sort();
+        bounds.dedup();
 
-            style_idx += 3;
-            if style_idx >= self.styles.len() {
-                break;
+        let mut runs: Vec<(Attributes, String)> = vec![];
+        // Column on the screen of the next run. We need it to expand tabs and to clip the line.
+        let mut column = 0;
+        for run in bounds.windows(2) {
+            let (start, end) = (run[0], run[1]);
+            let mut attributes = base;
+            for &(_, _, id) in spans.iter().filter(|span| span.0 <= start && end <= span.1) {
+                palette.apply(id, &mut attributes);
+            }
+
+            let mut printable = String::new();
+            width::push_printable(&mut printable, &text[start..end], &mut column, layout);
+            if printable.is_empty() {
+                // Scrolled out of the screen
+                continue;
+            }
+            match runs.last_mut() {
+                Some(&mut (previous, ref mut text)) if previous == attributes => {
+                    text.push_str(&printable)
+                }
+                _ => runs.push((attributes, printable)),
             }
         }
-        Ok(())
+
+        // The base attributes fill the rest of the row.
+        if base != Attributes::default() {
+            let first_column = ::std::cmp::max(column, layout.first_column);
+            let end_column = layout.first_column.saturating_add(layout.width);
+            let padding = end_column.saturating_sub(first_column) as usize;
+            runs.push((base, ::std::iter::repeat(' ').take(padding).collect()));
+        }
+        Ok(runs)
     }
 }
+
+/// Clamp the byte offset `offset` to `text`, and move it back to the closest character boundary.
+fn char_boundary(text: &str, offset: i64) -> usize {
+    if offset <= 0 {
+        return 0;
+    }
+    let mut offset = ::std::cmp::min(offset as usize, text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+#[cfg(test)]
+fn test_layout() -> LineLayout {
+    LineLayout {
+        tab_width: 4,
+        show_tabs: false,
+        first_column: 0,
+        width: 80,
+    }
+}
+
+#[test]
+fn add_styles_relative_offsets() {
+    use std::collections::HashMap;
+
+    use color::ColorSupport;
+    use style::Theme;
+
+    let line = Line {
+        text: "foo\tbar baz".into(),
+        styles: vec![0, 3, 0, 5, 3, 0],
+        ..Default::default()
+    };
+    let palette = Palette {
+        styles: &HashMap::new(),
+        theme: &Theme::default(),
+        colors: ColorSupport::TrueColor,
+    };
+    let invert = Attributes {
+        invert: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        line.styled_runs(&line.text, &palette, &test_layout(), Attributes::default())
+            .unwrap(),
+        vec![
+            (invert, "foo".to_owned()),
+            (Attributes::default(), " bar ".to_owned()),
+            (invert, "baz".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn add_styles_overlapping_spans() {
+    use std::collections::HashMap;
+
+    use color::ColorSupport;
+    use style::{Style, Theme};
+
+    // A keyword color over the whole line, the selection over "foo" and a search match over "t f"
+    let line = Line {
+        text: "let foo".into(),
+        styles: vec![0, 7, 2, -3, 3, 0, -5, 3, 1],
+        ..Default::default()
+    };
+    let mut styles = HashMap::new();
+    styles.insert(
+        2,
+        Style {
+            id: 2,
+            fg_color: Some(0xff_ff_00_00),
+            bg_color: None,
+            weight: None,
+            italic: None,
+        },
+    );
+    let theme = Theme {
+        selection: Some(0xff_00_00_ff),
+        find_highlight: Some(0xff_00_ff_00),
+        ..Default::default()
+    };
+    let palette = Palette {
+        styles: &styles,
+        theme: &theme,
+        colors: ColorSupport::TrueColor,
+    };
+    let keyword = Attributes {
+        fg: Some(0xff_ff_00_00),
+        ..Default::default()
+    };
+    let with_bg = |bg| Attributes {
+        bg: Some(bg),
+        ..keyword
+    };
+    assert_eq!(
+        line.styled_runs(&line.text, &palette, &test_layout(), Attributes::default())
+            .unwrap(),
+        vec![
+            (keyword, "le".to_owned()),
+            (with_bg(0xff_00_ff_00), "t ".to_owned()),
+            (with_bg(0xff_00_00_ff), "foo".to_owned()),
+        ]
+    );
+}
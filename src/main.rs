@@ -19,26 +19,44 @@ extern crate serde_derive;
 extern crate serde_json;
 
 extern crate termion;
+extern crate unicode_segmentation;
+extern crate unicode_width;
 
+mod color;
+mod commands;
 mod core;
 mod cursor;
 mod window;
 mod cache;
+mod clipboard;
 mod errors;
+mod grid;
 mod input;
+mod keymap;
+mod layout;
 mod line;
 mod operation;
+mod prompt;
+mod protocol;
 mod screen;
+mod settings;
+mod status_bar;
 mod style;
 mod update;
 mod view;
+mod width;
+
+use std::path::Path;
 
 use error_chain::ChainedError;
 
+use color::ColorSupport;
 use core::Core;
 use errors::*;
 use input::Input;
+use keymap::Keymap;
 use screen::Screen;
+use settings::Settings;
 use log::LogLevelFilter;
 use log4rs::append::file::FileAppender;
 use log4rs::config::{Appender, Config, Logger, Root};
@@ -77,47 +95,113 @@ fn run() -> Result<()> {
         (about: "The Xi Editor")
         (@arg core: -c --core +takes_value "Specify binary to use for the backend")
         (@arg logfile: -l --log-file +takes_value "Log file location")
-        (@arg file: +required "File to edit"));
+        (@arg colors: --colors +takes_value
+         "Colors supported by the terminal: truecolor, 256, 16 or mono. Detected by default")
+        (@arg tab_width: -t --tab-width +takes_value
+         "Width of a tab. Overrides the tab size configured in the core")
+        (@arg show_tabs: --show-tabs "Draw tabs with a visible glyph")
+        (@arg wrap: -w --wrap "Wrap long lines instead of scrolling horizontally")
+        (@arg highlight_line: --highlight-line
+         "Highlight the cursor line, if the theme has a color for it")
+        (@arg clipboard: --clipboard +takes_value
+         "Clipboard: osc52, xclip, wl-copy or internal. Detected by default")
+        (@arg keymap: -k --keymap +takes_value
+         "Key bindings file. Defaults to ~/.config/xi-tui/keymap.json")
+        (@arg line_numbers: -n --line-numbers +takes_value
+         "Line numbers in the gutter: none, absolute or relative. None by default")
+        (@arg file: +multiple
+         "Files to edit. An empty scratch buffer is opened if there is none"));
 
     let matches = xi.get_matches();
     let core_exe = matches.value_of("core").unwrap_or("xi-core");
     let logfile = matches.value_of("logfile").unwrap_or("xi-tui.log");
-    let file = matches.value_of("file").unwrap();
+    let files: Vec<&str> = matches
+        .values_of("file")
+        .map(|files| files.collect())
+        .unwrap_or_default();
+    let colors = match matches.value_of("colors") {
+        Some(colors) => colors.parse()?,
+        None => ColorSupport::detect(),
+    };
+    let mut settings = Settings::new(colors);
+    if let Some(tab_width) = matches.value_of("tab_width") {
+        settings.tab_width = Some(tab_width.parse().chain_err(|| {
+            ErrorKind::InvalidOption("tab-width".into(), tab_width.into())
+        })?);
+    }
+    settings.show_tabs = matches.is_present("show_tabs");
+    settings.wrap = matches.is_present("wrap");
+    settings.highlight_line = matches.is_present("highlight_line");
+    settings.clipboard = match matches.value_of("clipboard") {
+        Some(clipboard) => clipboard.parse()?,
+        None => clipboard::Backend::detect(),
+    };
+    if let Some(line_numbers) = matches.value_of("line_numbers") {
+        settings.line_numbers = line_numbers.parse()?;
+    }
 
     configure_logs(logfile);
-    let mut core = Core::new(core_exe);
+    let mut keymap = match matches.value_of("keymap") {
+        Some(path) => Keymap::load(Path::new(path))?,
+        None => match Keymap::default_path() {
+            Some(ref path) if path.exists() => Keymap::load(path)?,
+            _ => Keymap::new(),
+        },
+    };
+    let mut core = Core::new(core_exe, settings)?;
     let mut screen = Screen::new()?;
     let mut input = Input::new();
     input.run();
     screen.init()?;
-    core.open(file)?;
-    loop {
+    for file in &files {
+        core.open(file)?;
+    }
+    if files.is_empty() {
+        core.open_scratch()?;
+    }
+    // Returning drops the screen, which restores the terminal.
+    while !screen.is_quitting() {
         match screen.resize() {
             Ok(Some(new_size)) => {
-                info!("screen height changed. Notifying the core");
-                core.resize(new_size.1)?;
+                info!("screen size changed. Notifying the core");
+                core.resize(new_size.0, screen.view_height())?;
                 screen.schedule_update();
             }
             Err(e) => {
                 error!("failed to get new screen size");
-                log_error(&e);
+                log_error(&mut screen, &e);
             }
             _ => {}
         }
 
-        if let Ok(event) = input.try_recv() {
-            if let Err(e) = input::handle(&event, &mut core) {
-                log_error(&e);
+        if core.check_process() {
+            screen.schedule_update();
+        }
+
+        match core.process_responses() {
+            Ok(true) => screen.schedule_update(),
+            Ok(false) => {}
+            Err(e) => log_error(&mut screen, &e),
+        }
+
+        if let Ok(user_input) = input.try_recv() {
+            if let Err(e) = input::handle(&user_input, &mut keymap, &mut core, &mut screen) {
+                log_error(&mut screen, &e);
             }
         } else if let Err(e) = screen.update(&mut core) {
-            log_error(&e);
+            log_error(&mut screen, &e);
         }
     }
+    Ok(())
 }
 
-fn log_error<E: ChainedError>(e: &E) {
+/// Log an error and its causes, and show it in the status bar.
+fn log_error<E: ChainedError>(screen: &mut Screen, e: &E) {
     error!("error: {}", e);
+    let mut message = e.to_string();
     for e in e.iter().skip(1) {
         error!("caused by: {}", e);
+        message = format!("{}: {}", message, e);
     }
+    screen.set_message(message);
 }
@@ -0,0 +1,366 @@
+//! One-line prompt at the bottom of the screen, where commands and search queries are typed.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use termion::event::Key;
+
+use core::FindOptions;
+use grid::Grid;
+use layout::Rect;
+use settings::DEFAULT_TAB_WIDTH;
+use style::Attributes;
+use width;
+use width::LineLayout;
+
+/// Maximum number of entries in the history.
+const HISTORY_SIZE: usize = 100;
+
+/// What the prompt is for. Each kind has its own history.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PromptKind {
+    Command,
+    /// Incremental search
+    Find,
+    /// Search for the text to replace
+    Replace,
+    /// Replacement text
+    ReplaceWith,
+    /// Ask whether to replace the selected match. Keys are not inserted but submitted right away,
+    /// and the prompt stays open.
+    ConfirmReplace,
+    /// Path to save the buffer to
+    SaveAs,
+    /// Ask whether to create the missing directory of `save_path`. Like `ConfirmReplace`, keys
+    /// are submitted right away.
+    CreateDirectory,
+}
+
+impl PromptKind {
+    /// Return whether the prompt is a question answered with a single key.
+    fn is_question(&self) -> bool {
+        *self == PromptKind::ConfirmReplace || *self == PromptKind::CreateDirectory
+    }
+}
+
+/// What the prompt expects after a key was handled.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PromptEvent {
+    /// The text or the cursor changed.
+    Edited,
+    /// The prompt was closed, and its text must be run.
+    Submit(String),
+    /// The prompt was closed without running anything.
+    Cancel,
+    /// The word before the cursor must be completed, with `complete()`.
+    Complete,
+}
+
+#[derive(Clone, Debug)]
+pub struct Prompt {
+    active: bool,
+    kind: PromptKind,
+    text: String,
+    /// Byte offset of the cursor in `text`.
+    cursor: usize,
+    /// Submitted texts, the oldest first.
+    histories: HashMap<PromptKind, Vec<String>>,
+    /// Entry of the history being displayed, while browsing it.
+    history_index: Option<usize>,
+    /// Text that was being typed before browsing the history.
+    draft: String,
+    /// Options of the search prompts, toggled with Alt-C and Alt-R.
+    pub find_options: FindOptions,
+    /// Path the buffer is saved to once its directory is created, while asking whether to create
+    /// it.
+    pub save_path: Option<String>,
+}
+
+impl Prompt {
+    pub fn new() -> Self {
+        Prompt {
+            active: false,
+            kind: PromptKind::Command,
+            text: String::new(),
+            cursor: 0,
+            histories: HashMap::new(),
+            history_index: None,
+            draft: String::new(),
+            find_options: FindOptions::default(),
+            save_path: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn kind(&self) -> PromptKind {
+        self.kind
+    }
+
+    /// Show an empty prompt.
+    pub fn open(&mut self, kind: PromptKind) {
+        self.active = true;
+        self.kind = kind;
+        self.text.clear();
+        self.cursor = 0;
+        self.history_index = None;
+    }
+
+    pub fn close(&mut self) -> String {
+        self.active = false;
+        self.history_index = None;
+        self.cursor = 0;
+        ::std::mem::replace(&mut self.text, String::new())
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Insert `text` at the cursor. Only the first line is inserted, since the prompt has a
+    /// single line.
+    pub fn insert(&mut self, text: &str) {
+        let text = text.lines().next().unwrap_or("");
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    pub fn handle_key(&mut self, key: Key) -> PromptEvent {
+        let is_search = self.kind == PromptKind::Find || self.kind == PromptKind::Replace;
+        match key {
+            Key::Esc | Key::Ctrl('c') | Key::Ctrl('g') => {
+                self.close();
+                return PromptEvent::Cancel;
+            }
+            Key::Char(c) if self.kind.is_question() => return PromptEvent::Submit(c.to_string()),
+            _ if self.kind.is_question() => {}
+            Key::Char('\n') => {
+                let text = self.close();
+                let history = self.histories.entry(self.kind).or_insert_with(Vec::new);
+                if !text.trim().is_empty() && history.last() != Some(&text) {
+                    history.push(text.clone());
+                    if history.len() > HISTORY_SIZE {
+                        history.remove(0);
+                    }
+                }
+                return PromptEvent::Submit(text);
+            }
+            Key::Alt('c') if is_search => {
+                self.find_options.case_sensitive = !self.find_options.case_sensitive;
+            }
+            Key::Alt('r') if is_search => self.find_options.regex = !self.find_options.regex,
+            Key::Char('\t') => return PromptEvent::Complete,
+            Key::Char(c) => {
+                self.text.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+            }
+            Key::Backspace => if let Some(start) = self.previous_char() {
+                self.text.drain(start..self.cursor);
+                self.cursor = start;
+            },
+            Key::Delete | Key::Ctrl('d') => if let Some(end) = self.next_char() {
+                self.text.drain(self.cursor..end);
+            },
+            Key::Left | Key::Ctrl('b') => if let Some(start) = self.previous_char() {
+                self.cursor = start;
+            },
+            Key::Right | Key::Ctrl('f') => if let Some(end) = self.next_char() {
+                self.cursor = end;
+            },
+            Key::Home | Key::Ctrl('a') => self.cursor = 0,
+            Key::End | Key::Ctrl('e') => self.cursor = self.text.len(),
+            Key::Ctrl('u') => {
+                self.text.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::Ctrl('k') => self.text.truncate(self.cursor),
+            Key::Ctrl('w') => {
+                let before = self.text[..self.cursor].trim_end();
+                let start = before
+                    .rfind(char::is_whitespace)
+                    .map(|index| index + 1)
+                    .unwrap_or(0);
+                self.text.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            Key::Up | Key::Ctrl('p') => self.browse_history(-1),
+            Key::Down | Key::Ctrl('n') => self.browse_history(1),
+            _ => {}
+        }
+        PromptEvent::Edited
+    }
+
+    /// Replace the text from byte offset `start` to the cursor by the longest common prefix of
+    /// `candidates`. If there is a single candidate, a space is added after it, unless it's a
+    /// directory.
+    pub fn complete(&mut self, start: usize, candidates: &[String]) {
+        let prefix = match candidates.split_first() {
+            Some((first, rest)) => rest.iter().fold(first.as_str(), |prefix, candidate| {
+                common_prefix(prefix, candidate)
+            }),
+            None => return,
+        };
+        if start > self.cursor || prefix.len() < self.cursor - start {
+            return;
+        }
+        let mut completion = prefix.to_owned();
+        if candidates.len() == 1 && !completion.ends_with('/') {
+            completion.push(' ');
+        }
+        self.text.drain(start..self.cursor);
+        self.text.insert_str(start, &completion);
+        self.cursor = start + completion.len();
+    }
+
+    fn previous_char(&self) -> Option<usize> {
+        self.text[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map(|(index, _)| index)
+    }
+
+    fn next_char(&self) -> Option<usize> {
+        self.text[self.cursor..]
+            .chars()
+            .next()
+            .map(|c| self.cursor + c.len_utf8())
+    }
+
+    /// Show the previous (`delta` < 0) or next entry of the history.
+    fn browse_history(&mut self, delta: i64) {
+        let history = match self.histories.get(&self.kind) {
+            Some(history) => history,
+            None => return,
+        };
+        let index = match (self.history_index, delta < 0) {
+            (None, true) if !history.is_empty() => {
+                self.draft = self.text.clone();
+                Some(history.len() - 1)
+            }
+            (Some(index), true) if index > 0 => Some(index - 1),
+            (Some(index), false) if index + 1 < history.len() => Some(index + 1),
+            (Some(_), false) => None,
+            _ => return,
+        };
+        self.history_index = index;
+        self.text = match index {
+            Some(index) => history[index].clone(),
+            None => self.draft.clone(),
+        };
+        self.cursor = self.text.len();
+    }
+
+    /// Return the text displayed before what is typed.
+    fn sign(&self) -> String {
+        let options = match (self.find_options.case_sensitive, self.find_options.regex) {
+            (false, false) => "",
+            (true, false) => " [case]",
+            (false, true) => " [regex]",
+            (true, true) => " [case, regex]",
+        };
+        match self.kind {
+            PromptKind::Command => ":".into(),
+            PromptKind::Find => format!("find{}: ", options),
+            PromptKind::Replace => format!("replace{}: ", options),
+            PromptKind::ReplaceWith => "with: ".into(),
+            PromptKind::ConfirmReplace => "replace? (y)es, (n)o, (a)ll, (q)uit".into(),
+            PromptKind::SaveAs => "save as: ".into(),
+            PromptKind::CreateDirectory => {
+                let path = self.save_path.as_ref().map(|path| path.as_str()).unwrap_or("");
+                let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+                format!("create directory {}? (y)es, (n)o", directory.display())
+            }
+        }
+    }
+
+    /// Draw the prompt on row `y` of `grid`, and place the terminal cursor in it. The text is
+    /// scrolled horizontally so that the cursor is always visible.
+    pub fn render(&self, grid: &mut Grid, y: u16) {
+        let columns = grid.width();
+        let sign = self.sign();
+        let sign = width::truncate(&sign, columns, DEFAULT_TAB_WIDTH);
+        let sign_width = width::text_width(sign, DEFAULT_TAB_WIDTH);
+        let width = columns.saturating_sub(sign_width);
+        let cursor_column = width::text_width(&self.text[..self.cursor], DEFAULT_TAB_WIDTH);
+        let first_column = if cursor_column >= width {
+            cursor_column + 1 - width
+        } else {
+            0
+        };
+        let layout = LineLayout {
+            tab_width: DEFAULT_TAB_WIDTH,
+            show_tabs: false,
+            first_column: first_column,
+            width: width,
+        };
+        let mut output = String::new();
+        let mut column = 0;
+        width::push_printable(&mut output, &self.text, &mut column, &layout);
+
+        grid.clear(Rect {
+            x: 0,
+            y: y,
+            width: columns,
+            height: 1,
+        });
+        grid.put_str(0, y, sign, Attributes::default());
+        grid.put_str(sign_width, y, &output, Attributes::default());
+        grid.set_cursor(sign_width + cursor_column - first_column, y);
+    }
+}
+
+/// Return the longest common prefix of `a` and `b`.
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let end = a.char_indices()
+        .zip(b.chars())
+        .find(|&((_, x), y)| x != y)
+        .map(|((index, _), _)| index)
+        .unwrap_or_else(|| ::std::cmp::min(a.len(), b.len()));
+    &a[..end]
+}
+
+#[test]
+fn prompt_editing() {
+    let mut prompt = Prompt::new();
+    prompt.open(PromptKind::Command);
+    for c in "goto 12".chars() {
+        prompt.handle_key(Key::Char(c));
+    }
+    prompt.handle_key(Key::Left);
+    prompt.handle_key(Key::Backspace);
+    assert_eq!(prompt.text(), "goto 2");
+    prompt.handle_key(Key::Ctrl('w'));
+    assert_eq!(prompt.text(), "2");
+    prompt.handle_key(Key::End);
+    prompt.insert("0\nignored");
+    assert_eq!(
+        prompt.handle_key(Key::Char('\n')),
+        PromptEvent::Submit("20".into())
+    );
+    assert!(!prompt.is_active());
+
+    // History
+    prompt.open(PromptKind::Command);
+    prompt.insert("draft");
+    prompt.handle_key(Key::Up);
+    assert_eq!(prompt.text(), "20");
+    prompt.handle_key(Key::Up);
+    assert_eq!(prompt.text(), "20");
+    prompt.handle_key(Key::Down);
+    assert_eq!(prompt.text(), "draft");
+}
+
+#[test]
+fn prompt_completion() {
+    let mut prompt = Prompt::new();
+    prompt.open(PromptKind::Command);
+    prompt.insert("open sr");
+    prompt.complete(5, &["src/".into(), "srv/".into()]);
+    assert_eq!(prompt.text(), "open sr");
+    prompt.complete(5, &["src/".into()]);
+    assert_eq!(prompt.text(), "open src/");
+    prompt.complete(5, &["src/main.rs".into()]);
+    assert_eq!(prompt.text(), "open src/main.rs ");
+}
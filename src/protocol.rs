@@ -0,0 +1,358 @@
+//! Typed messages exchanged with xi-core.
+
+use serde_json;
+use serde_json::Value;
+
+use errors::*;
+use style::Style;
+use update::Update;
+
+/// A message received from the core.
+#[derive(Debug)]
+pub enum Message {
+    /// Response to the request with the given ID. The error is the raw error object sent by the
+    /// core.
+    Response(u64, ::std::result::Result<Value, Value>),
+    Notification(CoreNotification),
+    /// Notification that is not in `CoreNotification`, with its method. Depending on its version,
+    /// the core sends notifications we don't handle.
+    Ignored(String),
+}
+
+/// Methods of the notifications in `CoreNotification`.
+const NOTIFICATION_METHODS: &[&str] = &[
+    "update",
+    "scroll_to",
+    "set_style",
+    "available_themes",
+    "theme_changed",
+    "config_changed",
+    "available_plugins",
+    "plugin_started",
+    "plugin_stopped",
+    "update_cmds",
+    "alert",
+    "find_status",
+    "replace_status",
+];
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "method", content = "params")]
+#[serde(rename_all = "snake_case")]
+pub enum CoreNotification {
+    Update(UpdateParams),
+    ScrollTo(ScrollToParams),
+    SetStyle(Style),
+    AvailableThemes(AvailableThemesParams),
+    ThemeChanged(ThemeChangedParams),
+    ConfigChanged(ConfigChangedParams),
+    AvailablePlugins(AvailablePluginsParams),
+    PluginStarted(PluginParams),
+    PluginStopped(PluginParams),
+    UpdateCmds(UpdateCmdsParams),
+    Alert(AlertParams),
+    FindStatus(FindStatusParams),
+    ReplaceStatus(ReplaceStatusParams),
+}
+
+impl CoreNotification {
+    /// Return the view this notification is for, or `None` if it is not specific to a view.
+    pub fn view_id(&self) -> Option<&str> {
+        match *self {
+            CoreNotification::Update(ref params) => Some(&params.view_id),
+            CoreNotification::ScrollTo(ref params) => Some(&params.view_id),
+            CoreNotification::ConfigChanged(ref params) => Some(&params.view_id),
+            CoreNotification::AvailablePlugins(ref params) => Some(&params.view_id),
+            CoreNotification::PluginStarted(ref params) |
+            CoreNotification::PluginStopped(ref params) => Some(&params.view_id),
+            CoreNotification::UpdateCmds(ref params) => Some(&params.view_id),
+            CoreNotification::FindStatus(ref params) => Some(&params.view_id),
+            CoreNotification::ReplaceStatus(ref params) => Some(&params.view_id),
+            CoreNotification::SetStyle(_) |
+            CoreNotification::AvailableThemes(_) |
+            CoreNotification::ThemeChanged(_) |
+            CoreNotification::Alert(_) => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct UpdateParams {
+    pub view_id: String,
+    pub update: Update,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct ScrollToParams {
+    pub view_id: String,
+    pub line: u64,
+    pub col: u64,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct AvailableThemesParams {
+    pub themes: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct ThemeChangedParams {
+    pub name: String,
+    pub theme: ThemeSettings,
+}
+
+/// The colors of a theme that are not attached to a syntax style. The other settings of the
+/// theme are ignored.
+#[derive(Deserialize, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct ThemeSettings {
+    pub selection: Option<ThemeColor>,
+    pub selection_foreground: Option<ThemeColor>,
+    pub find_highlight: Option<ThemeColor>,
+    pub find_highlight_foreground: Option<ThemeColor>,
+    pub line_highlight: Option<ThemeColor>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct ThemeColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl ThemeColor {
+    /// Return the color as ARGB, like the colors of the styles.
+    pub fn argb(&self) -> u32 {
+        u32::from(self.a) << 24 | u32::from(self.r) << 16 | u32::from(self.g) << 8
+            | u32::from(self.b)
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct ConfigChangedParams {
+    pub view_id: String,
+    pub changes: ConfigChanges,
+}
+
+/// The configuration options that changed. Options we don't use are ignored.
+#[derive(Deserialize, Debug, PartialEq, Default)]
+pub struct ConfigChanges {
+    pub tab_size: Option<u16>,
+    pub translate_tabs_to_spaces: Option<bool>,
+    pub font_face: Option<String>,
+    pub font_size: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Plugin {
+    pub name: String,
+    pub running: bool,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct AvailablePluginsParams {
+    pub view_id: String,
+    pub plugins: Vec<Plugin>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct PluginParams {
+    pub view_id: String,
+    pub plugin: String,
+    /// Exit code, only set when the plugin stopped.
+    pub code: Option<i32>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct UpdateCmdsParams {
+    pub view_id: String,
+    pub plugin: String,
+    pub cmds: Vec<Value>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct AlertParams {
+    pub msg: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct FindStatusParams {
+    pub view_id: String,
+    pub queries: Vec<FindQuery>,
+}
+
+/// Status of one of the active searches. Fields we don't use are ignored.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct FindQuery {
+    pub chars: Option<String>,
+    /// Number of occurrences
+    pub matches: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct ReplaceStatusParams {
+    pub view_id: String,
+    pub status: Value,
+}
+
+/// A request or notification sent to the core.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "method", content = "params")]
+#[serde(rename_all = "snake_case")]
+pub enum CoreRequest {
+    NewView {
+        #[serde(skip_serializing_if = "Option::is_none")] file_path: Option<String>,
+    },
+    CloseView { view_id: String },
+    Save { view_id: String, file_path: String },
+    SetTheme { theme_name: String },
+    Edit(EditParams),
+}
+
+/// Parameters of an `edit` request. The parameters of the edit command itself depend on the
+/// command, so they are left untyped.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EditParams {
+    pub method: String,
+    pub view_id: String,
+    pub params: Value,
+}
+
+impl CoreRequest {
+    /// Serialize this request as a JSON-RPC message. Requests carry an `id`, while notifications
+    /// don't.
+    pub fn to_message(&self, id: Option<u64>) -> Result<Value> {
+        let mut message = serde_json::to_value(self).chain_err(|| {
+            error!("could not serialize the message to send");
+            ErrorKind::RpcError
+        })?;
+        if let Some(id) = id {
+            if let Some(object) = message.as_object_mut() {
+                object.insert("id".into(), json!(id));
+            }
+        }
+        Ok(message)
+    }
+}
+
+/// Decode a line received from the core.
+pub fn decode(line: &str) -> Result<Message> {
+    let value: Value = serde_json::from_str(line).chain_err(|| {
+        error!("could not deserialize core output as json");
+        ErrorKind::RpcError
+    })?;
+
+    if let Some(id) = value.get("id").and_then(|id| id.as_u64()) {
+        if let Some(result) = value.get("result") {
+            return Ok(Message::Response(id, Ok(result.clone())));
+        }
+        if let Some(error) = value.get("error") {
+            return Ok(Message::Response(id, Err(error.clone())));
+        }
+        error!("response {} has neither a result nor an error", id);
+        bail!(ErrorKind::RpcError);
+    }
+
+    if let Some(method) = value.get("method").and_then(|method| method.as_str()) {
+        if !NOTIFICATION_METHODS.contains(&method) {
+            return Ok(Message::Ignored(method.to_owned()));
+        }
+    }
+    let notification = serde_json::from_value(value).chain_err(|| {
+        error!("could not decode core notification: {}", line);
+        ErrorKind::RpcError
+    })?;
+    Ok(Message::Notification(notification))
+}
+
+#[test]
+fn decode_scroll_to() {
+    let line = r#"{"method":"scroll_to","params":{"view_id":"view-id-1","line":3,"col":7}}"#;
+    match decode(line).unwrap() {
+        Message::Notification(notification) => {
+            assert_eq!(notification.view_id(), Some("view-id-1"));
+            assert_eq!(
+                notification,
+                CoreNotification::ScrollTo(ScrollToParams {
+                    view_id: "view-id-1".into(),
+                    line: 3,
+                    col: 7,
+                })
+            );
+        }
+        message => panic!("unexpected message {:?}", message),
+    }
+}
+
+#[test]
+fn decode_response() {
+    match decode(r#"{"id":4,"result":"view-id-2"}"#).unwrap() {
+        Message::Response(4, Ok(result)) => assert_eq!(result, json!("view-id-2")),
+        message => panic!("unexpected message {:?}", message),
+    }
+    match decode(r#"{"id":5,"error":{"code":1}}"#).unwrap() {
+        Message::Response(5, Err(error)) => assert_eq!(error, json!({"code": 1})),
+        message => panic!("unexpected message {:?}", message),
+    }
+}
+
+#[test]
+fn decode_find_status() {
+    let line = r#"{"method":"find_status","params":{"view_id":"view-id-1","queries":[
+        {"id":1,"chars":"foo","case_sensitive":false,"is_regex":false,"matches":3}]}}"#;
+    match decode(line).unwrap() {
+        Message::Notification(CoreNotification::FindStatus(params)) => {
+            assert_eq!(params.queries[0].chars, Some("foo".into()));
+            assert_eq!(params.queries[0].matches, Some(3));
+        }
+        message => panic!("unexpected message {:?}", message),
+    }
+}
+
+#[test]
+fn decode_theme_changed() {
+    let line = r#"{"method":"theme_changed","params":{"name":"InspiredGitHub","theme":{
+        "caret":{"r":50,"g":50,"b":50,"a":255},"selection":{"r":200,"g":222,"b":255,"a":255},
+        "gutter_foreground":null}}}"#;
+    match decode(line).unwrap() {
+        Message::Notification(CoreNotification::ThemeChanged(params)) => {
+            assert_eq!(params.theme.selection.map(|c| c.argb()), Some(0xff_c8_de_ff));
+            assert_eq!(params.theme.line_highlight, None);
+        }
+        message => panic!("unexpected message {:?}", message),
+    }
+}
+
+#[test]
+fn decode_malformed() {
+    assert!(decode("not json").is_err());
+    assert!(decode(r#"{"method":"scroll_to","params":{"line":3}}"#).is_err());
+    assert!(decode(r#"{"method":"update","params":{}}"#).is_err());
+    match decode(r#"{"method":"unknown_method","params":{}}"#).unwrap() {
+        Message::Ignored(method) => assert_eq!(method, "unknown_method"),
+        message => panic!("unexpected message {:?}", message),
+    }
+}
+
+#[test]
+fn encode_request() {
+    let request = CoreRequest::NewView {
+        file_path: Some("foo.rs".into()),
+    };
+    assert_eq!(
+        request.to_message(Some(1)).unwrap(),
+        json!({"id": 1, "method": "new_view", "params": {"file_path": "foo.rs"}})
+    );
+    let request = CoreRequest::Edit(EditParams {
+        method: "insert".into(),
+        view_id: "view-id-1".into(),
+        params: json!({"chars": "a"}),
+    });
+    assert_eq!(
+        request.to_message(None).unwrap(),
+        json!({
+            "method": "edit",
+            "params": {"method": "insert", "view_id": "view-id-1", "params": {"chars": "a"}}
+        })
+    );
+}
@@ -4,8 +4,6 @@ use std::io::Write;
 use std::thread;
 use std::time;
 
-use serde_json;
-
 use termion;
 use termion::clear;
 use termion::cursor;
@@ -13,14 +11,34 @@ use termion::input::MouseTerminal;
 use termion::raw::{IntoRawMode, RawTerminal};
 use termion::screen::AlternateScreen;
 
+use color::ColorSupport;
 use core::Core;
-use style::Style;
 use errors::*;
+use grid::Grid;
+use layout::Direction;
+use prompt::Prompt;
+use status_bar::{StatusBar, STATUS_BAR_HEIGHT};
+use style::Attributes;
+
+/// In bracketed paste mode, the terminal sends pasted text between two markers, so that we can
+/// tell it apart from typed text.
+const ENABLE_BRACKETED_PASTE: &str = "\x1b[?2004h";
+const DISABLE_BRACKETED_PASTE: &str = "\x1b[?2004l";
 
 pub struct Screen {
     pub stdout: MouseTerminal<AlternateScreen<RawTerminal<std::io::Stdout>>>,
     pub size: (u16, u16),
     update_scheduled: bool,
+    /// Whether the main loop should exit. The terminal is restored when the screen is dropped.
+    quitting: bool,
+    show_buffer_list: bool,
+    status_bar: StatusBar,
+    pub prompt: Prompt,
+    /// Frame being drawn. Views only draw into it when they changed, so it is kept from one frame
+    /// to the next.
+    grid: Grid,
+    /// Frame displayed by the terminal, or `None` if the terminal must be entirely redrawn.
+    displayed: Option<Grid>,
 }
 
 impl Screen {
@@ -30,6 +48,12 @@ impl Screen {
             size: (0, 0),
             stdout: stdout,
             update_scheduled: false,
+            quitting: false,
+            show_buffer_list: false,
+            status_bar: StatusBar::new(),
+            prompt: Prompt::new(),
+            grid: Grid::new(0, 0),
+            displayed: None,
         })
     }
 
@@ -37,73 +61,190 @@ impl Screen {
         self.update_scheduled = true;
     }
 
-    /// Update the terminal size and return `true` if the height changed, and false otherwise.
+    /// Ask the main loop to exit.
+    pub fn quit(&mut self) {
+        self.quitting = true;
+    }
+
+    pub fn is_quitting(&self) -> bool {
+        self.quitting
+    }
+
+    /// Update the terminal size and return the new size if it changed.
     pub fn resize(&mut self) -> Result<Option<(u16, u16)>> {
         let new_size = termion::terminal_size().chain_err(|| ErrorKind::TerminalSizeError)?;
-        if self.size.1 == new_size.1 {
+        if self.size == new_size {
             Ok(None)
         } else {
             self.size = new_size;
+            self.grid = Grid::new(new_size.0, new_size.1);
+            self.displayed = None;
             Ok(Some(self.size))
         }
     }
 
+    /// Height available to draw the views.
+    pub fn view_height(&self) -> u16 {
+        self.size.1.saturating_sub(STATUS_BAR_HEIGHT)
+    }
+
+    /// Display `message` in the status bar, until it is cleared.
+    pub fn set_message(&mut self, message: String) {
+        self.status_bar.set_message(message);
+        self.schedule_update();
+    }
+
+    pub fn clear_message(&mut self) {
+        self.status_bar.clear_message();
+        self.schedule_update();
+    }
+
     pub fn init(&mut self) -> Result<()> {
-        write!(self.stdout, "{}{}", clear::All, cursor::Up(self.size.1))
-            .chain_err(|| ErrorKind::DisplayError)?;
+        write!(
+            self.stdout,
+            "{}{}{}",
+            ENABLE_BRACKETED_PASTE,
+            clear::All,
+            cursor::Up(self.size.1)
+        ).chain_err(|| ErrorKind::DisplayError)?;
         self.stdout.flush().chain_err(|| ErrorKind::DisplayError)?;
         Ok(())
     }
 
+    /// Show or hide the list of open buffers.
+    pub fn toggle_buffer_list(&mut self) {
+        self.show_buffer_list = !self.show_buffer_list;
+        self.schedule_update();
+    }
+
+    pub fn hide_buffer_list(&mut self) {
+        self.show_buffer_list = false;
+        self.schedule_update();
+    }
+
     pub fn update(&mut self, core: &mut Core) -> Result<()> {
-        if let Ok(msg) = core.update_rx.try_recv() {
-            let msg_list = msg.as_array().unwrap();
-            let (method, params) = (
-                msg_list[0].as_str().unwrap(),
-                msg_list[1].as_object().unwrap(),
-            );
-            match method {
-                "update" => {
-                    let update = serde_json::from_value(params.get("update").unwrap().clone())?;
-                    core.update(&update)?;
-                    self.schedule_update();
-                }
-                "scroll_to" => {
-                    // Deserialize the cursor position, and let the core update the view.
-                    let coord = (
-                        params.get("line").unwrap().as_u64().unwrap(),
-                        params.get("col").unwrap().as_u64().unwrap(),
-                    );
-                    core.scroll_to(coord)?;
-                    self.schedule_update();
-                }
-                "set_style" => {
-                    let style: Style =
-                        serde_json::from_value(params.get("set_style").unwrap().clone())?;
-                    core.get_view_mut()
-                        .ok_or_else(|| {
-                            error!("No view found");
-                            ErrorKind::DisplayError
-                        })?
-                        .set_style(style);
-                    self.schedule_update();
-                }
-                _ => {
-                    info!("Unknown request from backend {:?}", method);
-                }
+        if let Some(notification) = core.try_recv_notification() {
+            if core.handle_notification(notification?)? {
+                self.schedule_update();
             }
+            let back_to_pristine = core.get_view_mut()
+                .map(|view| view.take_back_to_pristine())
+                .unwrap_or(false);
+            if back_to_pristine {
+                self.set_message("no changes since the last save".into());
+            }
+        }
+        if let Some(sequence) = core.clipboard.take_escape_sequence() {
+            write!(self.stdout, "{}", sequence).chain_err(|| ErrorKind::ClipboardError)?;
+            self.stdout.flush().chain_err(|| ErrorKind::ClipboardError)?;
         }
         if self.update_scheduled {
             self.update_scheduled = false;
-            core.get_view_mut()
-                .ok_or_else(|| {
-                    error!("No view found");
-                    ErrorKind::DisplayError
-                })?
-                .render(&mut self.stdout)?
+            self.grid.hide_cursor();
+            if core.crash_report().is_some() {
+                self.render_crash_report(core);
+            } else if self.show_buffer_list {
+                self.render_buffer_list(core);
+            } else {
+                self.render_views(core)?;
+            }
+            self.flush(core.settings().colors)?;
         } else {
             thread::sleep(time::Duration::from_millis(10));
         }
         Ok(())
     }
+
+    fn render_views(&mut self, core: &mut Core) -> Result<()> {
+        self.render_separators(core);
+        // Views are opened asynchronously, so there may be nothing to draw yet.
+        let grid = &mut self.grid;
+        core.for_each_pane(|view| view.render(grid))?;
+        // The prompt replaces the status bar while it's open
+        let row = self.view_height();
+        if self.prompt.is_active() {
+            self.prompt.render(&mut self.grid, row);
+        } else {
+            self.status_bar.render(&mut self.grid, row, core.get_view());
+        }
+        Ok(())
+    }
+
+    /// Write to the terminal the cells that changed since the last frame.
+    fn flush(&mut self, colors: ColorSupport) -> Result<()> {
+        let output = self.grid.diff(self.displayed.as_ref(), colors);
+        write!(self.stdout, "{}", output).chain_err(|| ErrorKind::DisplayError)?;
+        self.stdout.flush().chain_err(|| ErrorKind::DisplayError)?;
+        self.displayed = Some(self.grid.clone());
+        Ok(())
+    }
+
+    fn render_crash_report(&mut self, core: &mut Core) {
+        let report = match core.crash_report() {
+            Some(report) => report.clone(),
+            None => return,
+        };
+        let mut lines = vec![format!("xi-core exited with status {}", report.status)];
+        if !report.stderr.is_empty() {
+            lines.push("".into());
+            lines.push("Last messages from the core:".into());
+            lines.extend(report.stderr.iter().map(|line| format!("    {}", line)));
+        }
+        lines.push("".into());
+        lines.push("Press r to restart the core and reopen the files, or ^C to quit.".into());
+
+        self.grid.clear_all();
+        for (index, line) in lines.iter().take(self.size.1 as usize).enumerate() {
+            self.grid
+                .put_str(0, index as u16, line, Attributes::default());
+        }
+
+        // The report replaced the content of the screen, so the views must be entirely redrawn
+        // once the core is restarted.
+        core.mark_dirty();
+    }
+
+    fn render_buffer_list(&mut self, core: &mut Core) {
+        self.grid.clear_all();
+
+        let current_view = core.current_view_id().to_owned();
+        for (index, (view_id, view)) in core.list_views().into_iter().enumerate() {
+            if index as u16 >= self.size.1 {
+                break;
+            }
+            let marker = if view_id == current_view { '*' } else { ' ' };
+            let entry = format!("{} {:>3} {}", marker, index + 1, view.name());
+            self.grid
+                .put_str(0, index as u16, &entry, Attributes::default());
+        }
+
+        // The list replaced the content of the screen, so the views must be entirely redrawn when
+        // the list is hidden.
+        core.mark_dirty();
+    }
+
+    /// Draw the bars between the panes.
+    fn render_separators(&mut self, core: &Core) {
+        for &(rect, direction) in core.layout().separators() {
+            match direction {
+                Direction::Horizontal => for y in rect.y..rect.y + rect.height {
+                    self.grid.put_str(rect.x, y, "│", Attributes::default());
+                },
+                Direction::Vertical => {
+                    let bar: String = ::std::iter::repeat('─')
+                        .take(rect.width as usize)
+                        .collect();
+                    self.grid
+                        .put_str(rect.x, rect.y, &bar, Attributes::default());
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = write!(self.stdout, "{}{}", DISABLE_BRACKETED_PASTE, cursor::Show);
+        let _ = self.stdout.flush();
+    }
 }
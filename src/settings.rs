@@ -0,0 +1,76 @@
+use std::str::FromStr;
+
+use clipboard;
+use color::ColorSupport;
+use errors::*;
+use style::Theme;
+
+/// Default width of a tab, when neither the command line nor the core configuration set it.
+pub const DEFAULT_TAB_WIDTH: u16 = 4;
+
+/// Display settings shared by all the views.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub colors: ColorSupport,
+    /// Tab width set on the command line. When set, it takes precedence over the `tab_size`
+    /// configured in the core.
+    pub tab_width: Option<u16>,
+    /// Draw tabs with a visible glyph instead of blank space.
+    pub show_tabs: bool,
+    /// Wrap the lines that are wider than the screen, instead of scrolling horizontally.
+    pub wrap: bool,
+    pub line_numbers: LineNumbers,
+    pub clipboard: clipboard::Backend,
+    /// Highlight the background of the cursor line, with the color of the theme.
+    pub highlight_line: bool,
+    pub theme: Theme,
+}
+
+impl Settings {
+    pub fn new(colors: ColorSupport) -> Self {
+        Settings {
+            colors: colors,
+            tab_width: None,
+            show_tabs: false,
+            wrap: false,
+            line_numbers: LineNumbers::None,
+            clipboard: clipboard::Backend::Internal,
+            highlight_line: false,
+            theme: Theme::default(),
+        }
+    }
+}
+
+/// How line numbers are displayed in the gutter, on the left of the text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineNumbers {
+    /// No gutter at all
+    None,
+    Absolute,
+    /// Distance to the cursor line. The cursor line itself shows its absolute number.
+    Relative,
+}
+
+impl LineNumbers {
+    /// Return the next mode, to cycle through the modes at runtime.
+    pub fn next(&self) -> LineNumbers {
+        match *self {
+            LineNumbers::None => LineNumbers::Absolute,
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::None,
+        }
+    }
+}
+
+impl FromStr for LineNumbers {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LineNumbers> {
+        match s {
+            "none" => Ok(LineNumbers::None),
+            "absolute" => Ok(LineNumbers::Absolute),
+            "relative" => Ok(LineNumbers::Relative),
+            _ => bail!(ErrorKind::InvalidOption("line-numbers".into(), s.into())),
+        }
+    }
+}
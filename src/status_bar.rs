@@ -0,0 +1,73 @@
+use grid::Grid;
+use settings::DEFAULT_TAB_WIDTH;
+use style::Attributes;
+use view::View;
+use width;
+
+/// Number of rows taken by the status bar at the bottom of the screen.
+pub const STATUS_BAR_HEIGHT: u16 = 1;
+
+#[derive(Clone, Debug)]
+pub struct StatusBar {
+    /// Last error message, displayed until it is cleared.
+    message: Option<String>,
+}
+
+impl StatusBar {
+    pub fn new() -> Self {
+        StatusBar { message: None }
+    }
+
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    pub fn clear_message(&mut self) {
+        self.message = None;
+    }
+
+    /// Draw the status bar on row `y` of `grid`.
+    pub fn render(&self, grid: &mut Grid, y: u16, view: Option<&View>) {
+        let columns = grid.width();
+        let (left, right) = match view {
+            Some(view) => {
+                // The core gives the cursor column as a byte offset. Until the line is
+                // received, that's the best we have.
+                let (line, offset) = view.cursor();
+                let column = view.cursor_column().map(u64::from).unwrap_or(offset);
+                let modified = if view.is_pristine() { "" } else { " [+]" };
+                let matches = match view.find_matches() {
+                    Some(1) => "1 match  ".to_owned(),
+                    Some(matches) => format!("{} matches  ", matches),
+                    None => String::new(),
+                };
+                (
+                    format!(" {}{}", view.name(), modified),
+                    format!("{}{}:{} ", matches, line + 1, column + 1),
+                )
+            }
+            None => (" [no file]".to_owned(), String::new()),
+        };
+
+        let mut text = left;
+        if let Some(ref message) = self.message {
+            text.push_str("  ");
+            text.push_str(message);
+        }
+
+        // Truncate the left part so that the cursor position is always visible, and pad the space
+        // in between so that the bar covers the whole line.
+        let right = width::truncate(&right, columns, DEFAULT_TAB_WIDTH);
+        let left_width = columns.saturating_sub(width::text_width(right, DEFAULT_TAB_WIDTH));
+        let mut bar = width::truncate(&text, left_width, DEFAULT_TAB_WIDTH).to_owned();
+        let padding = left_width - width::text_width(&bar, DEFAULT_TAB_WIDTH);
+        bar.extend(::std::iter::repeat(' ').take(padding as usize));
+        bar.push_str(right);
+
+        let invert = Attributes {
+            invert: true,
+            ..Default::default()
+        };
+        grid.put_str(0, y, &bar, invert);
+    }
+}
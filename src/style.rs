@@ -1,3 +1,22 @@
+use std::collections::HashMap;
+
+#[cfg(test)]
+use termion::color;
+use termion::style;
+
+use color::ColorSupport;
+use protocol::{ThemeColor, ThemeSettings};
+
+/// Style of the selections. The core reserves it and never sends it with `set_style`.
+pub const SELECTION_STYLE_ID: u16 = 0;
+
+/// Style of the search matches. Like the selection style, it is reserved.
+pub const FIND_STYLE_ID: u16 = 1;
+
+/// Colors of the search matches: black on yellow.
+const FIND_FG: u32 = 0xff_00_00_00;
+const FIND_BG: u32 = 0xff_ff_d7_00;
+
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct Style {
     pub id: u16,
@@ -14,3 +33,211 @@ pub struct Style {
     /// default false
     pub italic: Option<bool>,
 }
+
+impl Style {
+    /// Apply this style over `attributes`. Attributes that are not set are left untouched.
+    pub fn apply(&self, attributes: &mut Attributes) {
+        if let Some(fg) = self.fg_color.and_then(opaque) {
+            attributes.fg = Some(fg);
+        }
+        if let Some(bg) = self.bg_color.and_then(opaque) {
+            attributes.bg = Some(bg);
+        }
+        if self.weight.unwrap_or(400) >= 700 {
+            attributes.bold = true;
+        }
+        if self.italic.unwrap_or(false) {
+            attributes.italic = true;
+        }
+    }
+}
+
+/// Colors of the editor itself, as opposed to the syntax styles, from the theme of the core. They
+/// are ARGB values, and `None` when the theme doesn't set them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Theme {
+    pub selection: Option<u32>,
+    pub selection_foreground: Option<u32>,
+    pub find_highlight: Option<u32>,
+    pub find_highlight_foreground: Option<u32>,
+    /// Background of the cursor line
+    pub line_highlight: Option<u32>,
+}
+
+impl<'a> From<&'a ThemeSettings> for Theme {
+    fn from(settings: &'a ThemeSettings) -> Theme {
+        let argb = |color: Option<ThemeColor>| color.and_then(|color| opaque(color.argb()));
+        Theme {
+            selection: argb(settings.selection),
+            selection_foreground: argb(settings.selection_foreground),
+            find_highlight: argb(settings.find_highlight),
+            find_highlight_foreground: argb(settings.find_highlight_foreground),
+            line_highlight: argb(settings.line_highlight),
+        }
+    }
+}
+
+/// What is needed to turn the style spans of a line into attributes.
+pub struct Palette<'a> {
+    /// The styles sent by the core, by ID
+    pub styles: &'a HashMap<u16, Style>,
+    pub theme: &'a Theme,
+    pub colors: ColorSupport,
+}
+
+impl<'a> Palette<'a> {
+    /// Apply the style `id` over `attributes`. The reserved styles, which the core doesn't send,
+    /// use the colors of the theme when the terminal can display them.
+    pub fn apply(&self, id: u16, attributes: &mut Attributes) {
+        if let Some(style) = self.styles.get(&id) {
+            style.apply(attributes);
+            return;
+        }
+        let has_colors = self.colors != ColorSupport::Mono;
+        match id {
+            SELECTION_STYLE_ID if has_colors && self.theme.selection.is_some() => {
+                attributes.bg = self.theme.selection;
+                if self.theme.selection_foreground.is_some() {
+                    attributes.fg = self.theme.selection_foreground;
+                }
+            }
+            FIND_STYLE_ID if has_colors && self.theme.find_highlight.is_some() => {
+                attributes.bg = self.theme.find_highlight;
+                if self.theme.find_highlight_foreground.is_some() {
+                    attributes.fg = self.theme.find_highlight_foreground;
+                }
+            }
+            FIND_STYLE_ID if has_colors => {
+                attributes.fg = Some(FIND_FG);
+                attributes.bg = Some(FIND_BG);
+            }
+            FIND_STYLE_ID => attributes.underline = true,
+            // The selection, and any style we don't know about
+            _ => attributes.invert = true,
+        }
+    }
+}
+
+/// Return the precedence of the style `id` when spans overlap: the selection is drawn over the
+/// search matches, which are drawn over the syntax styles.
+pub fn layer(id: u16) -> u8 {
+    match id {
+        SELECTION_STYLE_ID => 2,
+        FIND_STYLE_ID => 1,
+        _ => 0,
+    }
+}
+
+/// Attributes of a run of text, once all the styles that cover it are combined.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Attributes {
+    /// ARGB foreground color
+    pub fg: Option<u32>,
+    /// ARGB background color
+    pub bg: Option<u32>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub invert: bool,
+}
+
+impl Attributes {
+    /// Return the escape sequence that switches from the `previous` attributes to these ones, with
+    /// the colors the terminal supports. Attributes can't be turned off one by one on every
+    /// terminal, so if one must be turned off, everything is reset and set again.
+    pub fn transition(&self, previous: &Attributes, colors: ColorSupport) -> String {
+        if self == previous {
+            return String::new();
+        }
+        let removed = (previous.fg.is_some() && self.fg.is_none())
+            || (previous.bg.is_some() && self.bg.is_none())
+            || (previous.bold && !self.bold) || (previous.italic && !self.italic)
+            || (previous.underline && !self.underline)
+            || (previous.invert && !self.invert);
+        let (mut escape, previous) = if removed {
+            (format!("{}", style::Reset), Attributes::default())
+        } else {
+            (String::new(), *previous)
+        };
+
+        if let Some(fg) = self.fg.filter(|fg| previous.fg != Some(*fg)) {
+            escape.push_str(&colors.fg(fg));
+        }
+        if let Some(bg) = self.bg.filter(|bg| previous.bg != Some(*bg)) {
+            escape.push_str(&colors.bg(bg));
+        }
+        if self.bold && !previous.bold {
+            escape.push_str(&format!("{}", style::Bold));
+        }
+        if self.italic && !previous.italic {
+            escape.push_str(&format!("{}", style::Italic));
+        }
+        if self.underline && !previous.underline {
+            escape.push_str(&format!("{}", style::Underline));
+        }
+        if self.invert && !previous.invert {
+            escape.push_str(&format!("{}", style::Invert));
+        }
+        escape
+    }
+}
+
+/// Despite what the protocol documentation says, the core encodes colors as ARGB. Fully
+/// transparent colors are considered unset.
+fn opaque(argb: u32) -> Option<u32> {
+    if argb >> 24 == 0 {
+        None
+    } else {
+        Some(argb)
+    }
+}
+
+#[test]
+fn style_escape() {
+    let style = Style {
+        id: 2,
+        fg_color: Some(0xff_10_20_30),
+        bg_color: Some(0),
+        weight: Some(700),
+        italic: None,
+    };
+    let mut attributes = Attributes::default();
+    style.apply(&mut attributes);
+    let none = Attributes::default();
+    assert_eq!(
+        attributes.transition(&none, ColorSupport::TrueColor),
+        format!("{}{}", color::Fg(color::Rgb(0x10, 0x20, 0x30)), style::Bold)
+    );
+    assert_eq!(
+        attributes.transition(&none, ColorSupport::Ansi256),
+        format!("{}{}", color::Fg(color::AnsiValue(234)), style::Bold)
+    );
+    assert_eq!(
+        attributes.transition(&none, ColorSupport::Mono),
+        format!("{}", style::Bold)
+    );
+}
+
+#[test]
+fn attribute_transitions() {
+    let colors = ColorSupport::TrueColor;
+    let bold = Attributes {
+        bold: true,
+        ..Default::default()
+    };
+    let bold_inverted = Attributes {
+        invert: true,
+        ..bold
+    };
+    assert_eq!(bold.transition(&bold, colors), "");
+    // Only what changed is sent...
+    assert_eq!(
+        bold_inverted.transition(&bold, colors),
+        format!("{}", style::Invert)
+    );
+    // ...unless something must be turned off
+    assert_eq!(
+        bold.transition(&bold_inverted, colors),
+        format!("{}{}", style::Reset, style::Bold)
+    );
+}
@@ -1,80 +1,376 @@
-use std::io::Write;
 use std::collections::HashMap;
 
-use termion::clear;
-use termion::cursor;
-
 use cache::LineCache;
 use cursor::Cursor;
 use errors::*;
-use style::Style;
+use grid::Grid;
+use line::Line;
+use settings::{LineNumbers, Settings, DEFAULT_TAB_WIDTH};
+use style::{Attributes, Palette, Style};
 use update::Update;
+use width;
+use width::LineLayout;
+use layout::Rect;
 use window::Window;
 
-const TAB_LENGTH: u16 = 4;
+/// What a pane shows of a view: the lines in its window, the area of the screen they're drawn in,
+/// and whether the pane is focused. A view displayed in several panes is displayed through the
+/// viewport of each pane in turn.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Viewport {
+    window: Window,
+    rect: Rect,
+    focused: bool,
+}
 
 #[derive(Clone, Debug)]
 pub struct View {
     last_rev: u64,
-    pub filepath: String,
+    /// Path of the file, or `None` for a scratch buffer that was never saved.
+    pub filepath: Option<String>,
     cache: LineCache,
     cursor: Cursor,
     window: Window,
     styles: HashMap<u16, Style>,
+    /// Whether the buffer is unmodified since it was last saved.
+    pristine: bool,
+    /// Whether the buffer became pristine again since the last call to `take_back_to_pristine`,
+    /// because the changes were undone or saved.
+    back_to_pristine: bool,
+    settings: Settings,
+    /// Tab width configured in the core for this view.
+    config_tab_width: u16,
+    /// Area of the screen the view is drawn in, including the gutter.
+    rect: Rect,
+    /// Whether the view is in the focused pane. The main cursor of the other views is drawn like
+    /// the secondary cursors, since the terminal cursor is in the focused pane.
+    focused: bool,
+    /// Number of matches of the current search, if any.
+    find_matches: Option<u64>,
 }
 
 impl View {
-    pub fn new(filepath: &str) -> View {
+    pub fn new(filepath: Option<String>, settings: &Settings) -> View {
         View {
             last_rev: 0,
-            filepath: filepath.to_owned(),
+            filepath: filepath,
             cache: LineCache::new(),
             cursor: Cursor::new(),
             window: Window::new(),
             styles: HashMap::new(),
+            pristine: true,
+            back_to_pristine: false,
+            settings: settings.clone(),
+            config_tab_width: DEFAULT_TAB_WIDTH,
+            rect: Rect::default(),
+            focused: true,
+            find_matches: None,
+        }
+    }
+
+    /// Return the name displayed for this view: its path, or `[scratch]`.
+    pub fn name(&self) -> &str {
+        self.filepath.as_ref().map(|path| path.as_str()).unwrap_or("[scratch]")
+    }
+
+    pub fn set_settings(&mut self, settings: &Settings) {
+        if &self.settings != settings {
+            self.settings = settings.clone();
+            self.mark_dirty();
+            self.refresh_layout();
+        }
+    }
+
+    /// Set the tab width configured in the core. It is ignored if the tab width is set in the
+    /// settings.
+    pub fn set_config_tab_width(&mut self, tab_width: u16) {
+        if self.config_tab_width != tab_width {
+            self.config_tab_width = tab_width;
+            self.mark_dirty();
+            self.refresh_layout();
         }
     }
 
+    pub fn tab_width(&self) -> u16 {
+        self.settings.tab_width.unwrap_or(self.config_tab_width)
+    }
+
     pub fn set_style(&mut self, style: Style) {
         self.styles.insert(style.id, style);
     }
 
     pub fn update_lines(&mut self, update: &Update) -> Result<()> {
-        self.cache.update(update)
+        self.back_to_pristine = update.pristine && !self.pristine;
+        self.pristine = update.pristine;
+        self.cache.update(update)?;
+        // The cursor line may have changed, so its column may have changed too.
+        self.refresh_layout();
+        Ok(())
+    }
+
+    pub fn is_pristine(&self) -> bool {
+        self.pristine
+    }
+
+    pub fn set_find_matches(&mut self, matches: Option<u64>) {
+        self.find_matches = matches;
+    }
+
+    pub fn find_matches(&self) -> Option<u64> {
+        self.find_matches
+    }
+
+    /// Return whether the buffer became pristine again since the last call.
+    pub fn take_back_to_pristine(&mut self) -> bool {
+        ::std::mem::replace(&mut self.back_to_pristine, false)
     }
 
     pub fn update_cursor(&mut self, cursor_pos: (u64, u64)) {
+        // Relative line numbers and the line highlight depend on the cursor line
+        let relative = self.settings.line_numbers == LineNumbers::Relative;
+        if (relative || self.settings.highlight_line) && cursor_pos.0 != self.cursor.line {
+            self.mark_dirty();
+        }
         self.cursor.update(cursor_pos);
-        self.window.update(&self.cursor.clone());
+        if !self.settings.wrap {
+            self.window.update(&self.cursor.clone());
+        }
+        self.refresh_layout();
+    }
+
+    /// Scroll the window so that the cursor is visible, and when lines are wrapped, compute how
+    /// many rows the lines in the window take.
+    fn refresh_layout(&mut self) {
+        self.layout_window(true);
+    }
+
+    /// Lay out the lines in the window like `refresh_layout`, but only scroll to the cursor if
+    /// `follow_cursor` is set.
+    fn layout_window(&mut self, follow_cursor: bool) {
+        // The gutter grows with the number of lines
+        let width = self.rect.width.saturating_sub(self.gutter_width());
+        self.window.set_width(width);
+
+        let position = if follow_cursor {
+            self.cursor_position()
+        } else {
+            None
+        };
+
+        if !self.settings.wrap {
+            self.window.set_heights(vec![]);
+            if let Some((_, column)) = position {
+                self.window.update_column(column);
+            }
+            return;
+        }
+
+        self.window.reset_first_column();
+        let layout = self.layout();
+        let wrap = self.settings.wrap;
+        let lines = self.cache.lines();
+        let line_height = |index: u64| {
+            lines
+                .get(index as usize)
+                .map(|line| wrap_columns(line, &layout, wrap).len() as u16)
+                .unwrap_or(1)
+        };
+
+        if let Some((row, _)) = position {
+            self.window
+                .update_wrapped(self.cursor.line, row, &line_height);
+        }
+
+        let mut heights = vec![];
+        let mut rows = 0;
+        let mut index = self.window.start();
+        while rows < self.window.size() && (index as usize) < lines.len() {
+            let height = line_height(index);
+            heights.push(height);
+            rows = rows.saturating_add(height);
+            index += 1;
+        }
+        self.window.set_heights(heights);
+    }
+
+    /// Return the number of columns taken by the gutter: enough for the largest line number, and a
+    /// space to separate it from the text.
+    fn gutter_width(&self) -> u16 {
+        if self.settings.line_numbers == LineNumbers::None {
+            return 0;
+        }
+        let nb_lines = ::std::cmp::max(self.cache.lines().len(), 1);
+        nb_lines.to_string().len() as u16 + 1
+    }
+
+    /// Return the number displayed in the gutter for line `index`.
+    fn line_number(&self, index: u64) -> u64 {
+        match self.settings.line_numbers {
+            LineNumbers::Relative if index > self.cursor.line => index - self.cursor.line,
+            LineNumbers::Relative if index < self.cursor.line => self.cursor.line - index,
+            _ => index + 1,
+        }
+    }
+
+    /// Return the cursor position as `(row, column)`, where `row` is the row within the cursor
+    /// line when it is wrapped, and `column` is the column within that row. Return `None` if the
+    /// cursor line is not available.
+    fn cursor_position(&self) -> Option<(u16, u16)> {
+        let line = self.cursor_line()?;
+        let column =
+            width::offset_to_column(&line.text, self.cursor.column as usize, self.tab_width());
+        let rows = wrap_columns(line, &self.layout(), self.settings.wrap);
+        let row = rows.iter().rposition(|start| *start <= column).unwrap_or(0);
+        Some((row as u16, column - rows[row]))
+    }
+
+    /// Return how the lines of this view are laid out on the screen.
+    fn layout(&self) -> LineLayout {
+        LineLayout {
+            tab_width: self.tab_width(),
+            show_tabs: self.settings.show_tabs,
+            first_column: if self.settings.wrap {
+                0
+            } else {
+                self.window.first_column()
+            },
+            width: self.window.width(),
+        }
+    }
+
+    /// When lines are wrapped, return the position the cursor would have after moving by `delta`
+    /// rows on the screen, as a `(line, offset)` position within the buffer. Return `None` if the
+    /// lines are not wrapped, in which case moving by rows is the same as moving by lines, or if
+    /// the target line is not available.
+    pub fn move_by_rows(&self, delta: i64) -> Option<(u64, u64)> {
+        if !self.settings.wrap {
+            return None;
+        }
+        let (row, column) = self.cursor_position()?;
+        let layout = self.layout();
+        let wrap = self.settings.wrap;
+        let lines = self.cache.lines();
+
+        let mut line = self.cursor.line;
+        let mut row = i64::from(row) + delta;
+        while row < 0 {
+            if line == 0 {
+                return Some((0, 0));
+            }
+            line -= 1;
+            let line = lines.get(line as usize).filter(|line| line.is_valid)?;
+            row += wrap_columns(line, &layout, wrap).len() as i64;
+        }
+        loop {
+            let text = lines.get(line as usize).filter(|line| line.is_valid)?;
+            let rows = wrap_columns(text, &layout, wrap);
+            if row < rows.len() as i64 {
+                let row = row as usize;
+                let mut target = rows[row] + column;
+                // Stay on the target row if it's shorter than the current one
+                if let Some(next_row) = rows.get(row + 1) {
+                    target = ::std::cmp::min(target, next_row - 1);
+                }
+                let offset = width::column_to_offset(&text.text, target, layout.tab_width);
+                return Some((line, offset as u64));
+            }
+            row -= rows.len() as i64;
+            line += 1;
+        }
+    }
+
+    /// Force the whole view to be redrawn on the next render.
+    pub fn mark_dirty(&mut self) {
+        self.window.mark_dirty();
+    }
+
+    /// Return the cursor position, as `(line, column)`.
+    pub fn cursor(&self) -> (u64, u64) {
+        (self.cursor.line, self.cursor.column)
+    }
+
+    /// Return the column of the cursor within its line, with tabs expanded and wide characters
+    /// counted as two columns. Return `None` if the cursor line is not available.
+    pub fn cursor_column(&self) -> Option<u16> {
+        let line = self.cursor_line()?;
+        Some(width::offset_to_column(
+            &line.text,
+            self.cursor.column as usize,
+            self.tab_width(),
+        ))
+    }
+
+    fn cursor_line(&self) -> Option<&Line> {
+        self.cache
+            .lines()
+            .get(self.cursor.line as usize)
+            .and_then(|line| if line.is_valid { Some(line) } else { None })
     }
 
     pub fn get_window(&self) -> (u64, u64) {
         (self.window.start(), self.window.end())
     }
 
-    pub fn render<W: Write>(&mut self, w: &mut W) -> Result<()> {
+    /// Draw the view in its area of the grid. The terminal cursor is placed on the main cursor if
+    /// the view is focused.
+    pub fn render(&mut self, grid: &mut Grid) -> Result<()> {
         if self.cache.is_dirty() || self.window.is_dirty() {
-            write!(w, "{}{}", cursor::Goto(1, 1), clear::All)
-                .chain_err(|| ErrorKind::DisplayError)?;
-
-            self.render_lines(w)?;
+            grid.clear(self.rect);
+            self.render_lines(grid)?;
             self.cache.mark_clean();
             self.window.mark_clean();
         }
 
-        self.render_cursor(w)?;
+        if self.focused {
+            self.render_cursor(grid);
+        }
 
         Ok(())
     }
 
-    pub fn resize(&mut self, height: u16) -> bool {
+    /// Move the view to the area `rect` of the screen. Return `true` if the view must be redrawn.
+    /// The view only scrolls to the cursor if it's focused, since the view of another pane may
+    /// be scrolled away from the cursor it shares with the focused pane.
+    pub fn resize(&mut self, rect: Rect) -> bool {
+        // The view clears its whole area when it's redrawn, which also erases what was drawn in
+        // it before it moved or changed size.
+        if rect != self.rect {
+            self.mark_dirty();
+        }
         let cursor_line = self.cursor.line;
         let nb_lines = self.cache.lines().len() as u64;
-        self.window.resize(height, cursor_line, nb_lines);
+        self.window.resize(rect.height, cursor_line, nb_lines);
+        self.rect = rect;
+        self.layout_window(self.focused);
         self.window.is_dirty()
     }
 
-    fn render_lines<W: Write>(&self, w: &mut W) -> Result<()> {
+    /// Display the view through `viewport`, and put the viewport it used so far in its place.
+    pub fn swap_viewport(&mut self, viewport: &mut Viewport) {
+        ::std::mem::swap(&mut self.window, &mut viewport.window);
+        ::std::mem::swap(&mut self.rect, &mut viewport.rect);
+        ::std::mem::swap(&mut self.focused, &mut viewport.focused);
+        // The lines may have changed since the viewport was last used.
+        self.layout_window(false);
+    }
+
+    /// Return a copy of the viewport the view is displayed through.
+    pub fn viewport(&self) -> Viewport {
+        Viewport {
+            window: self.window.clone(),
+            rect: self.rect,
+            focused: self.focused,
+        }
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        if self.focused != focused {
+            self.focused = focused;
+            self.mark_dirty();
+        }
+    }
+
+    fn render_lines(&self, grid: &mut Grid) -> Result<()> {
         debug!("Rendering lines");
 
         // Get the lines that are within the displayed window
@@ -84,74 +380,193 @@ impl View {
             .skip(self.window.start() as usize)
             .take(self.window.size() as usize);
 
+        let layout = self.layout();
+        let wrap = self.settings.wrap;
+        let gutter_width = self.gutter_width();
+        let palette = Palette {
+            styles: &self.styles,
+            theme: &self.settings.theme,
+            colors: self.settings.colors,
+        };
+
         // Draw the valid lines within this range
         for (lineno, line) in lines.enumerate() {
+            let index = self.window.start() + lineno as u64;
+            // Get the line vertical offset so that we know where to draw it. When lines are
+            // wrapped, the last lines may not fit in the window.
+            let line_pos = match self.window.offset(index) {
+                Some(line_pos) => line_pos,
+                None => break,
+            };
+
             if !line.is_valid {
                 continue;
             }
 
-            // Get the line vertical offset so that we know where to draw it.
-            let line_pos = self.window
-                .offset(self.window.start() + lineno as u64)
-                .ok_or_else(|| {
-                    error!("Could not find line position within the window");
-                    ErrorKind::DisplayError
-                })?;
+            let mut base = Attributes::default();
+            if self.settings.highlight_line && index == self.cursor.line {
+                base.bg = self.settings.theme.line_highlight;
+            }
+
+            // Draw each row of the line, as long as it fits in the window
+            let rows = wrap_columns(line, &layout, wrap);
+            for row_index in 0..rows.len() {
+                let row = line_pos + row_index as u16;
+                if row >= self.window.size() {
+                    break;
+                }
+                line.render(
+                    grid,
+                    self.rect.x + gutter_width,
+                    self.rect.y + row,
+                    &palette,
+                    &row_layout(&layout, &rows, row_index),
+                    base,
+                )?;
+            }
+            self.render_secondary_cursors(grid, index, line, line_pos);
 
-            line.render(w, line_pos + 1)?;
+            if gutter_width > 0 {
+                let number = format!(
+                    "{:>width$}",
+                    self.line_number(index),
+                    width = gutter_width as usize - 1
+                );
+                grid.put_str(
+                    self.rect.x,
+                    self.rect.y + line_pos,
+                    &number,
+                    Attributes::default(),
+                );
+            }
         }
         Ok(())
     }
 
-    pub fn render_cursor<W: Write>(&self, w: &mut W) -> Result<()> {
+    /// Draw the cursors of line `index` as inverted cells, except the main cursor if the terminal
+    /// cursor is drawn on it. The line starts on row `line_pos`.
+    fn render_secondary_cursors(&self, grid: &mut Grid, index: u64, line: &Line, line_pos: u16) {
+        let layout = self.layout();
+        let rows = wrap_columns(line, &layout, self.settings.wrap);
+        for &offset in &line.cursors {
+            if self.focused && (index, offset) == self.cursor() {
+                continue;
+            }
+            let offset = ::std::cmp::min(offset as usize, line.text.len());
+            let column = width::offset_to_column(&line.text, offset, layout.tab_width);
+            let row = rows.iter().rposition(|start| *start <= column).unwrap_or(0);
+            if line_pos + row as u16 >= self.window.size() {
+                continue;
+            }
+            // At the end of the line, the cursor is drawn on an empty cell.
+            let grapheme = match width::grapheme_at(&line.text, offset) {
+                "" => " ",
+                grapheme => grapheme,
+            };
+            let row_layout = row_layout(&layout, &rows, row);
+            let mut cell = String::new();
+            width::push_printable(&mut cell, grapheme, &mut column.clone(), &row_layout);
+            if cell.is_empty() {
+                // Scrolled out of the screen
+                continue;
+            }
+            let invert = Attributes {
+                invert: true,
+                ..Default::default()
+            };
+            grid.put_str(
+                self.rect.x + self.gutter_width() + column.saturating_sub(row_layout.first_column),
+                self.rect.y + line_pos + row as u16,
+                &cell,
+                invert,
+            );
+        }
+    }
+
+    /// Convert a position on the screen, relative to the top left corner of the view, to a
+    /// `(line, offset)` position within the buffer, as the core expects it for mouse events.
+    pub fn screen_to_buffer(&self, row: u16, column: u16) -> (u64, u64) {
+        let column = column.saturating_sub(self.gutter_width());
+        let (line, row) = self.window.line_at(row);
+        let layout = self.layout();
+        let wrap = self.settings.wrap;
+        let offset = self.cache
+            .lines()
+            .get(line as usize)
+            .map(|line| {
+                let rows = wrap_columns(line, &layout, wrap);
+                let row = ::std::cmp::min(row as usize, rows.len() - 1);
+                let mut column = column.saturating_add(layout.first_column + rows[row]);
+                if let Some(next_row) = rows.get(row + 1) {
+                    column = ::std::cmp::min(column, next_row - 1);
+                }
+                width::column_to_offset(&line.text, column, layout.tab_width)
+            })
+            .unwrap_or(0);
+        (line, offset as u64)
+    }
+
+    /// Place the terminal cursor on the main cursor. It stays hidden if the main cursor is
+    /// scrolled out of the view, so that the rest of the frame is still displayed.
+    pub fn render_cursor(&self, grid: &mut Grid) {
         debug!("Rendering cursor");
         if !self.window.is_within_window(self.cursor.line) {
-            error!(
+            debug!(
                 "Cursor is on line {} which is not within the displayed window",
                 self.cursor.line
             );
-            bail!(ErrorKind::DisplayError)
+            return;
         }
 
-        // Get the line that has the cursor
-        let line = self.cache
-            .lines()
-            .get(self.cursor.line as usize)
-            .and_then(|line| if line.is_valid { Some(line) } else { None })
-            .ok_or_else(|| {
-                error!("No valid line at cursor index {}", self.cursor.line);
-                ErrorKind::DisplayError
-            })?;
-
         // Get the line vertical offset so that we know where to draw it.
-        let line_pos = self.window.offset(self.cursor.line).ok_or_else(|| {
-            error!("Could not find line position within the window: {:?}", line);
-            ErrorKind::DisplayError
-        })?;
-
-        // Calculate the cursor position on the line. The trick is that we know the position within
-        // the string, but characters may have various lengths. For the moment, we only handle
-        // tabs, and we assume the terminal has tabstops of TAB_LENGTH. We consider that all the
-        // other characters have a width of 1.
-        let column = line.text
-            .chars()
-            .take(self.cursor.column as usize)
-            .fold(0, add_char_width);
+        let line_pos = match self.window.offset(self.cursor.line) {
+            Some(line_pos) => line_pos,
+            None => {
+                error!("Could not find line position within the window");
+                return;
+            }
+        };
+
+        // Calculate the cursor position on the line. The core gives us a byte offset within the
+        // line, but characters may have various widths on the screen, and the line may be
+        // wrapped or scrolled horizontally.
+        let (row, column) = match self.cursor_position() {
+            Some(position) => position,
+            None => {
+                error!("No valid line at cursor index {}", self.cursor.line);
+                return;
+            }
+        };
+        let column = column.saturating_sub(self.layout().first_column) + self.gutter_width();
+        let row = line_pos + row;
+        if row >= self.window.size() || column >= self.rect.width {
+            debug!("Cursor at row {} column {} is out of the view", row, column);
+            return;
+        }
 
         // Draw the cursor
-        let cursor_pos = cursor::Goto(column as u16 + 1, line_pos + 1);
-        write!(w, "{}", cursor_pos).chain_err(|| ErrorKind::DisplayError)?;
-        debug!("Cursor set at line {} column {}", line_pos, column);
-        w.flush().chain_err(|| ErrorKind::DisplayError)?;
+        grid.set_cursor(self.rect.x + column, self.rect.y + row);
+        debug!("Cursor set at line {} column {}", row, column);
+    }
+}
 
-        Ok(())
+/// Return the columns at which each row of `line` starts. Unless lines are wrapped, there is a
+/// single row starting at column 0.
+fn wrap_columns(line: &Line, layout: &LineLayout, wrap: bool) -> Vec<u16> {
+    if !wrap {
+        return vec![0];
     }
+    width::wrap(&line.text, layout.width, layout.tab_width)
 }
 
-fn add_char_width(acc: u16, c: char) -> u16 {
-    if c == '\t' {
-        acc + TAB_LENGTH - (acc % TAB_LENGTH)
-    } else {
-        acc + 1
+/// Return the layout of row `index` of a line that starts a new row at each column of `rows`.
+fn row_layout(layout: &LineLayout, rows: &[u16], index: usize) -> LineLayout {
+    let start = rows[index];
+    LineLayout {
+        first_column: layout.first_column + start,
+        width: rows.get(index + 1)
+            .map(|next| next - start)
+            .unwrap_or(layout.width),
+        ..*layout
     }
 }
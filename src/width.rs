@@ -0,0 +1,287 @@
+//! Conversions between positions within a line as the core sends them (UTF-8 byte offsets) and
+//! columns on the screen.
+//!
+//! A line is displayed as a sequence of grapheme clusters, whose width is given by the East Asian
+//! Width of their characters: 2 columns for CJK characters and most emoji, 1 for a letter and its
+//! combining marks. Tabs extend to the next tabstop, every `tab_width` columns.
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Character displayed in place of control characters, which would otherwise be interpreted by
+/// the terminal.
+pub const REPLACEMENT_CHAR: char = '\u{fffd}';
+
+/// Return the width of the grapheme cluster `grapheme` when displayed at column `column`.
+pub fn grapheme_width(grapheme: &str, column: u16, tab_width: u16) -> u16 {
+    if grapheme == "\t" {
+        let tab_width = ::std::cmp::max(tab_width, 1);
+        return tab_width - (column % tab_width);
+    }
+    if grapheme.chars().any(|c| c.is_control()) {
+        return 1;
+    }
+    ::std::cmp::min(UnicodeWidthStr::width(grapheme), 2) as u16
+}
+
+/// Character drawn in the first column of a tab, when tabs are made visible.
+pub const TAB_CHAR: char = '→';
+
+/// How a line is drawn on the screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineLayout {
+    pub tab_width: u16,
+    /// Draw tabs with a visible glyph.
+    pub show_tabs: bool,
+    /// First column that is drawn. The columns before are scrolled out of the screen.
+    pub first_column: u16,
+    /// Number of columns that are drawn.
+    pub width: u16,
+}
+
+impl LineLayout {
+    fn end_column(&self) -> u16 {
+        self.first_column.saturating_add(self.width)
+    }
+}
+
+/// Append to `output` the text that must be written to the terminal to display `text`, starting
+/// at column `*column`, and update `*column`. Only the columns visible according to `layout` are
+/// written. Tabs are expanded to spaces, so that we don't depend on the terminal tabstops, and
+/// control characters are replaced so that they can't mess up the display.
+pub fn push_printable(output: &mut String, text: &str, column: &mut u16, layout: &LineLayout) {
+    let (first, end) = (layout.first_column, layout.end_column());
+    for grapheme in text.graphemes(true) {
+        let start = *column;
+        let width = grapheme_width(grapheme, start, layout.tab_width);
+        *column = start.saturating_add(width);
+
+        // A zero-width grapheme is visible if it's in the visible columns, even at the first one.
+        let hidden = if width == 0 {
+            start < first || start >= end
+        } else {
+            *column <= first || start >= end
+        };
+        if hidden {
+            continue;
+        }
+        // Number of columns of this grapheme that are actually visible
+        let visible = ::std::cmp::min(*column, end) - ::std::cmp::max(start, first);
+
+        if grapheme == "\t" {
+            let mut spaces = visible;
+            if layout.show_tabs && start >= first {
+                output.push(TAB_CHAR);
+                spaces -= 1;
+            }
+            output.extend(::std::iter::repeat(' ').take(spaces as usize));
+        } else if visible < width {
+            // A wide character that is cut by the edge of the screen
+            output.extend(::std::iter::repeat(' ').take(visible as usize));
+        } else if grapheme.chars().any(|c| c.is_control()) {
+            output.push(REPLACEMENT_CHAR);
+        } else {
+            output.push_str(grapheme);
+        }
+    }
+}
+
+/// Return the grapheme cluster that starts at byte offset `offset` of `text`, or an empty string
+/// at the end of the line.
+pub fn grapheme_at(text: &str, offset: usize) -> &str {
+    if !text.is_char_boundary(offset) {
+        return "";
+    }
+    match text[offset..].graphemes(true).next() {
+        Some("\n") | Some("\r\n") | None => "",
+        Some(grapheme) => grapheme,
+    }
+}
+
+/// Return the column at which the character at byte offset `offset` is displayed. If `offset` is
+/// in the middle of a grapheme cluster, the column of the cluster is returned.
+pub fn offset_to_column(text: &str, offset: usize, tab_width: u16) -> u16 {
+    let mut column = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        if index >= offset || grapheme == "\n" || grapheme == "\r\n" {
+            break;
+        }
+        let width = grapheme_width(grapheme, column, tab_width);
+        if offset < index + grapheme.len() {
+            break;
+        }
+        column = column.saturating_add(width);
+    }
+    column
+}
+
+/// Return the byte offset of the grapheme cluster displayed at column `column`. Columns past the
+/// end of the line map to the end of the line (before the trailing newline, if any).
+pub fn column_to_offset(text: &str, column: u16, tab_width: u16) -> usize {
+    let mut current = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        if grapheme == "\n" || grapheme == "\r\n" {
+            return index;
+        }
+        let width = grapheme_width(grapheme, current, tab_width);
+        if column < current + width || (width == 0 && column == current) {
+            return index;
+        }
+        current = current.saturating_add(width);
+    }
+    text.len()
+}
+
+/// Return the columns at which each row starts, when `text` is wrapped to rows of `width`
+/// columns. Rows are broken after whitespace when possible, and anywhere otherwise. Whitespace is
+/// allowed to overflow the end of a row, so that rows never start with the space that separates
+/// two words.
+pub fn wrap(text: &str, width: u16, tab_width: u16) -> Vec<u16> {
+    let mut rows = vec![0];
+    if width == 0 {
+        return rows;
+    }
+    let mut row_start: u16 = 0;
+    let mut column: u16 = 0;
+    // Column right after the last whitespace of the current row
+    let mut break_column = None;
+
+    for grapheme in text.graphemes(true) {
+        if grapheme == "\n" || grapheme == "\r\n" {
+            break;
+        }
+        let end = column.saturating_add(grapheme_width(grapheme, column, tab_width));
+        let is_whitespace = grapheme.chars().all(char::is_whitespace);
+
+        if end > row_start.saturating_add(width) && column > row_start && !is_whitespace {
+            row_start = match break_column {
+                Some(break_column) if break_column > row_start => break_column,
+                _ => column,
+            };
+            rows.push(row_start);
+            break_column = None;
+        }
+        if is_whitespace {
+            break_column = Some(end);
+        }
+        column = end;
+    }
+    rows
+}
+
+/// Return the width of `text` on the screen.
+pub fn text_width(text: &str, tab_width: u16) -> u16 {
+    offset_to_column(text, text.len(), tab_width)
+}
+
+/// Return the longest prefix of `text` that fits in `columns` columns.
+pub fn truncate(text: &str, columns: u16, tab_width: u16) -> &str {
+    &text[..column_to_offset(text, columns, tab_width)]
+}
+
+#[test]
+fn ascii_columns() {
+    assert_eq!(offset_to_column("hello", 0, 4), 0);
+    assert_eq!(offset_to_column("hello", 3, 4), 3);
+    assert_eq!(column_to_offset("hello", 3, 4), 3);
+    assert_eq!(column_to_offset("hello\n", 10, 4), 5);
+    assert_eq!(text_width("hello\n", 4), 5);
+}
+
+#[test]
+fn tab_columns() {
+    assert_eq!(offset_to_column("\tfoo", 1, 4), 4);
+    assert_eq!(offset_to_column("ab\tc", 3, 4), 4);
+    assert_eq!(column_to_offset("ab\tc", 3, 4), 2);
+    assert_eq!(column_to_offset("ab\tc", 4, 4), 3);
+    assert_eq!(offset_to_column("ab\tc", 3, 8), 8);
+}
+
+#[test]
+fn printable_text() {
+    let mut layout = LineLayout {
+        tab_width: 4,
+        show_tabs: false,
+        first_column: 0,
+        width: 80,
+    };
+    let mut output = String::new();
+    let mut column = 1;
+    push_printable(&mut output, "a\tb\x1b", &mut column, &layout);
+    assert_eq!(output, "a  b\u{fffd}");
+    assert_eq!(column, 6);
+
+    layout.show_tabs = true;
+    let mut output = String::new();
+    let mut column = 0;
+    push_printable(&mut output, "\tb", &mut column, &layout);
+    assert_eq!(output, "→   b");
+
+    // A zero-width grapheme at the first column is kept.
+    let mut output = String::new();
+    let mut column = 0;
+    push_printable(&mut output, "\u{200b}a", &mut column, &layout);
+    assert_eq!(output, "\u{200b}a");
+}
+
+#[test]
+fn printable_clipped_text() {
+    let layout = LineLayout {
+        tab_width: 4,
+        show_tabs: true,
+        first_column: 3,
+        width: 4,
+    };
+    let mut output = String::new();
+    let mut column = 0;
+    push_printable(&mut output, "ab\tcd日", &mut column, &layout);
+    // The tab starts before the first visible column, so its glyph is not drawn, and the wide
+    // character only has one visible column.
+    assert_eq!(output, " cd ");
+    assert_eq!(column, 8);
+}
+
+#[test]
+fn wrap_rows() {
+    assert_eq!(wrap("short", 10, 4), vec![0]);
+    assert_eq!(wrap("foo bar baz\n", 8, 4), vec![0, 8]);
+    // Whitespace overflows the end of the row
+    assert_eq!(wrap("aaaa bbbb", 4, 4), vec![0, 5]);
+    // Words longer than a row are broken anywhere
+    assert_eq!(wrap("abcdefghij", 4, 4), vec![0, 4, 8]);
+    // Wide characters are never split
+    assert_eq!(wrap("日本語", 5, 4), vec![0, 4]);
+}
+
+#[test]
+fn wide_columns() {
+    // Each of these CJK characters is 3 bytes long and 2 columns wide
+    let text = "日本語x";
+    assert_eq!(offset_to_column(text, 3, 4), 2);
+    assert_eq!(offset_to_column(text, 9, 4), 6);
+    assert_eq!(column_to_offset(text, 2, 4), 3);
+    // The second column of a wide character belongs to that character
+    assert_eq!(column_to_offset(text, 3, 4), 3);
+    assert_eq!(column_to_offset(text, 6, 4), 9);
+    assert_eq!(truncate(text, 3, 4), "日");
+}
+
+#[test]
+fn combining_columns() {
+    // "e" followed by a combining acute accent, then "x"
+    let text = "e\u{301}x";
+    assert_eq!(text_width(text, 4), 2);
+    assert_eq!(offset_to_column(text, 3, 4), 1);
+    // An offset in the middle of a cluster maps to the cluster
+    assert_eq!(offset_to_column(text, 1, 4), 0);
+    assert_eq!(column_to_offset(text, 1, 4), 3);
+}
+
+#[test]
+fn graphemes_at_offsets() {
+    assert_eq!(grapheme_at("e\u{301}t\n", 0), "e\u{301}");
+    assert_eq!(grapheme_at("e\u{301}t\n", 3), "t");
+    assert_eq!(grapheme_at("e\u{301}t\n", 4), "");
+    assert_eq!(grapheme_at("e\u{301}t", 5), "");
+    assert_eq!(grapheme_at("é", 1), "");
+}
@@ -1,9 +1,15 @@
 use cursor::Cursor;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Window {
     start: u64,
     size: u16,
+    /// First column that is displayed. It is non-zero when the window is scrolled horizontally.
+    first_column: u16,
+    width: u16,
+    /// Number of rows taken by each line of the window, starting from `start`, when lines are
+    /// wrapped. When empty, each line takes exactly one row.
+    heights: Vec<u16>,
     dirty: bool,
 }
 
@@ -12,6 +18,9 @@ impl Window {
         Window {
             start: 0,
             size: 0,
+            first_column: 0,
+            width: 0,
+            heights: vec![],
             dirty: true,
         }
     }
@@ -24,6 +33,10 @@ impl Window {
         self.dirty = false;
     }
 
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
     pub fn update(&mut self, cursor: &Cursor) {
         if cursor.line < self.start() {
             self.start = cursor.line;
@@ -34,6 +47,67 @@ impl Window {
         }
     }
 
+    /// Scroll vertically so that row `row` of line `line` is visible, when lines are wrapped.
+    /// `line_height` returns the number of rows a line takes.
+    pub fn update_wrapped<F: Fn(u64) -> u16>(&mut self, line: u64, row: u16, line_height: F) {
+        if line < self.start {
+            self.start = line;
+            self.dirty = true;
+            return;
+        }
+
+        // Number of rows from the top of the window to the cursor row, included.
+        let mut rows: u64 = (self.start..line)
+            .map(|l| u64::from(line_height(l)))
+            .sum::<u64>() + u64::from(row) + 1;
+        while rows > u64::from(self.size) && self.start < line {
+            rows -= u64::from(line_height(self.start));
+            self.start += 1;
+            self.dirty = true;
+        }
+    }
+
+    /// Set the number of rows taken by each line, starting from the first line of the window.
+    pub fn set_heights(&mut self, heights: Vec<u16>) {
+        if self.heights != heights {
+            self.heights = heights;
+            self.dirty = true;
+        }
+    }
+
+    /// Scroll horizontally so that `column` is visible.
+    pub fn update_column(&mut self, column: u16) {
+        if column < self.first_column {
+            self.first_column = column;
+            self.dirty = true;
+        } else if column >= self.first_column.saturating_add(self.width) {
+            self.first_column = 1 + column - ::std::cmp::max(self.width, 1);
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_width(&mut self, width: u16) {
+        if self.width != width {
+            self.width = width;
+            self.dirty = true;
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn first_column(&self) -> u16 {
+        self.first_column
+    }
+
+    pub fn reset_first_column(&mut self) {
+        if self.first_column != 0 {
+            self.first_column = 0;
+            self.dirty = true;
+        }
+    }
+
     pub fn resize(&mut self, height: u16, cursor: u64, last_line: u64) {
         if self.size == height {
             return;
@@ -90,16 +164,45 @@ impl Window {
     }
 
     pub fn is_within_window(&self, index: u64) -> bool {
-        if self.start <= index && index < self.end() {
-            return true;
-        }
-        false
+        self.offset(index).is_some()
     }
 
+    /// Return the row at which the line `index` starts, relative to the top of the window.
     pub fn offset(&self, index: u64) -> Option<u16> {
-        if !self.is_within_window(index) {
+        if index < self.start || index >= self.end() {
             return None;
         }
-        Some(((index - self.start) & u64::from(u16::max_value())) as u16)
+        if self.heights.is_empty() {
+            return Some(((index - self.start) & u64::from(u16::max_value())) as u16);
+        }
+        let offset: u64 = self.heights
+            .iter()
+            .take((index - self.start) as usize)
+            .map(|height| u64::from(*height))
+            .sum();
+        if offset < u64::from(self.size) {
+            Some(offset as u16)
+        } else {
+            None
+        }
+    }
+
+    /// Return the line displayed at row `row` of the window, and the row within that line.
+    pub fn line_at(&self, row: u16) -> (u64, u16) {
+        if self.heights.is_empty() {
+            return (self.start + u64::from(row), 0);
+        }
+        let mut first_row = 0;
+        for (index, height) in self.heights.iter().enumerate() {
+            if row < first_row + height {
+                return (self.start + index as u64, row - first_row);
+            }
+            first_row += height;
+        }
+        // Past the last line: act as if the remaining lines took one row each
+        (
+            self.start + self.heights.len() as u64 + u64::from(row - first_row),
+            0,
+        )
     }
 }